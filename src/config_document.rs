@@ -0,0 +1,475 @@
+#![forbid(unsafe_code)]
+
+use core::fmt;
+
+use crate::config_parser::*;
+
+/// Lossless view of a config file: parameters plus the comments and
+/// whitespace around them, so values can be rewritten without touching the
+/// rest of the layout.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigDocument {
+    nodes: Vec<ConfigNode>,
+}
+
+#[derive(Debug, Clone)]
+enum ConfigNode {
+    // Comments, whitespace and lines that failed to parse.
+    Trivia(String),
+    Entry(ConfigEntry),
+}
+
+#[derive(Debug, Clone)]
+struct ConfigEntry {
+    name: String,
+    assign: String,
+    value: String,
+    val: ConfigVal,
+    profile: Option<String>,
+}
+
+impl ConfigEntry {
+    // Whether the entry takes part when `profile` is selected.
+    fn is_active(&self, profile: Option<&str>) -> bool {
+        self.profile.is_none() || self.profile.as_deref() == profile
+    }
+}
+
+impl ConfigDocument {
+    pub fn parse(slurp_config: &str) -> Self {
+        let mut nodes = Vec::new();
+        let mut last_idx = 0;
+
+        let mut parser = ConfigParser::new(slurp_config);
+        while let Some(res) = parser.parse_spanned() {
+            if let Ok((param, span)) = res {
+                if last_idx < span.name.start {
+                    nodes.push(ConfigNode::Trivia(
+                        slurp_config[last_idx..span.name.start].to_string(),
+                    ));
+                }
+
+                let profile = param.profile().map(str::to_string);
+                let (name, val) = param.split();
+                nodes.push(ConfigNode::Entry(ConfigEntry {
+                    name,
+                    assign: slurp_config[span.name.end..span.value.start].to_string(),
+                    value: slurp_config[span.value.clone()].to_string(),
+                    val,
+                    profile,
+                }));
+                last_idx = span.value.end;
+            }
+        }
+
+        if last_idx < slurp_config.len() {
+            nodes.push(ConfigNode::Trivia(slurp_config[last_idx..].to_string()));
+        }
+
+        Self { nodes }
+    }
+
+    // Last entry of `name` in the section of `profile`.
+    fn find_mut(&mut self, name: &str, profile: Option<&str>) -> Option<&mut ConfigEntry> {
+        self.nodes.iter_mut().rev().find_map(|node| match node {
+            ConfigNode::Entry(entry)
+                if entry.name == name && entry.profile.as_deref() == profile =>
+            {
+                Some(entry)
+            }
+            _ => None,
+        })
+    }
+
+    /// Value of `name` that takes effect when `profile` is selected.
+    pub fn get(&self, name: &str, profile: Option<&str>) -> Option<&ConfigVal> {
+        self.nodes.iter().rev().find_map(|node| match node {
+            ConfigNode::Entry(entry) if entry.name == name && entry.is_active(profile) => {
+                Some(&entry.val)
+            }
+            _ => None,
+        })
+    }
+
+    /// Comment lines right above the entry of `name` outside of any profile
+    /// section.
+    pub fn doc(&self, name: &str) -> Option<String> {
+        let idx = self.nodes.iter().position(|node| match node {
+            ConfigNode::Entry(entry) => entry.name == name && entry.profile.is_none(),
+            _ => false,
+        })?;
+        let trivia = match idx.checked_sub(1).map(|idx| &self.nodes[idx]) {
+            Some(ConfigNode::Trivia(trivia)) => trivia,
+            _ => return Some(String::new()),
+        };
+
+        let mut lines = trivia.split('\n').collect::<Vec<_>>();
+        // Indentation of the entry.
+        let _ = lines.pop();
+        if idx > 1 {
+            // The rest of the previous entry line.
+            let _ = lines.remove(0);
+        }
+        let doc_start = lines
+            .iter()
+            .rposition(|line| !line.trim_start().starts_with('#'))
+            .map_or(0, |idx| idx + 1);
+        Some(lines[doc_start..].join("\n"))
+    }
+
+    /// Update the entry of `param.name()` in the section of `profile` or
+    /// insert a new one preceded by `doc` after the last entry of that
+    /// section. A missing profile section is added at the end.
+    pub fn set(&mut self, param: &ConfigParam, profile: Option<&str>, doc: &str) {
+        if let Some(entry) = self.find_mut(param.name(), profile) {
+            // Keep the original spelling (e.g. `${VAR}`) of unchanged values.
+            if entry.val == *param.val() {
+                return;
+            }
+            entry.value = param.val().to_string();
+            entry.val = param.val().clone();
+            return;
+        }
+
+        let entry = ConfigNode::Entry(ConfigEntry {
+            name: param.name().to_string(),
+            assign: " :: ".to_string(),
+            value: param.val().to_string(),
+            val: param.val().clone(),
+            profile: profile.map(str::to_string),
+        });
+        let doc = if doc.is_empty() {
+            String::new()
+        } else {
+            format!("{}\n", doc)
+        };
+
+        let last_idx = self.nodes.iter().rposition(|node| match node {
+            ConfigNode::Entry(entry) => entry.profile.as_deref() == profile,
+            _ => false,
+        });
+        let idx = match (last_idx, profile) {
+            (Some(last_idx), _) => last_idx + 1,
+            (None, None) => {
+                let after = if self.nodes.is_empty() { "\n" } else { "\n\n" };
+                let nodes = vec![
+                    ConfigNode::Trivia(doc),
+                    entry,
+                    ConfigNode::Trivia(after.to_string()),
+                ];
+                let _ = self.nodes.splice(0..0, nodes);
+                return;
+            }
+            (None, Some(profile)) => {
+                self.insert_into_empty_profile(profile, entry, doc);
+                return;
+            }
+        };
+
+        // Keep the rest of the line (e.g. a trailing comment) with its entry.
+        let (idx, after) = match self.nodes.get_mut(idx) {
+            Some(ConfigNode::Trivia(rest)) => {
+                let after = rest.find('\n').map(|new_line| rest.split_off(new_line));
+                (idx + 1, after)
+            }
+            _ => (idx, None),
+        };
+        let nodes = vec![
+            ConfigNode::Trivia(format!("\n\n{}", doc)),
+            entry,
+            ConfigNode::Trivia(after.unwrap_or_else(|| "\n".to_string())),
+        ];
+        let _ = self.nodes.splice(idx..idx, nodes);
+    }
+
+    // Insert `entry` right under the `[profile name]` header, which is added
+    // at the end when there is none.
+    fn insert_into_empty_profile(&mut self, profile: &str, entry: ConfigNode, doc: String) {
+        let is_header = |line: &str| {
+            let line = line.trim();
+            let words = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+                .map(|line| line.split_whitespace().collect::<Vec<_>>());
+            words.as_deref() == Some(&["profile", profile])
+        };
+
+        for idx in 0..self.nodes.len() {
+            let trivia = match &mut self.nodes[idx] {
+                ConfigNode::Trivia(trivia) => trivia,
+                _ => continue,
+            };
+            let mut line_start = 0;
+            for line in trivia.split_inclusive('\n') {
+                let line_end = line_start + line.trim_end_matches('\n').len();
+                if is_header(line) {
+                    // The new line of the header ends the entry line instead.
+                    let mut after = trivia.split_off(line_end);
+                    if after.is_empty() {
+                        after.push('\n');
+                    }
+                    trivia.push('\n');
+                    trivia.push_str(&doc);
+                    let nodes = vec![entry, ConfigNode::Trivia(after)];
+                    let _ = self.nodes.splice(idx + 1..idx + 1, nodes);
+                    return;
+                }
+                line_start += line.len();
+            }
+        }
+
+        let before = match self.nodes.last() {
+            None => "",
+            Some(ConfigNode::Trivia(trivia)) if trivia.ends_with('\n') => "\n",
+            Some(_) => "\n\n",
+        };
+        self.nodes.push(ConfigNode::Trivia(format!(
+            "{}[profile {}]\n{}",
+            before, profile, doc
+        )));
+        self.nodes.push(entry);
+        self.nodes.push(ConfigNode::Trivia("\n".to_string()));
+    }
+
+    /// Normalize spacing around `::`, value syntax, indentation and blank
+    /// lines while keeping comments and parameter order.
+    pub fn format(&mut self) {
+        if let Some(ConfigNode::Entry(_)) = self.nodes.last() {
+            self.nodes.push(ConfigNode::Trivia(String::new()));
+        }
+
+        let len = self.nodes.len();
+        for (idx, node) in self.nodes.iter_mut().enumerate() {
+            match node {
+                ConfigNode::Entry(entry) => {
+                    entry.assign = " :: ".to_string();
+                    if !entry.value.contains("${") {
+                        entry.value = entry.val.to_string();
+                    }
+                }
+                ConfigNode::Trivia(trivia) => {
+                    *trivia = format_trivia(trivia, idx == 0, idx + 1 == len);
+                }
+            }
+        }
+    }
+}
+
+fn format_trivia(trivia: &str, is_first: bool, is_last: bool) -> String {
+    let mut pieces = trivia.split('\n').map(str::trim).collect::<Vec<_>>();
+    let mut formatted = String::with_capacity(trivia.len());
+
+    if !is_first {
+        // The rest of the previous entry line, e.g. a trailing comment.
+        let rest = pieces.remove(0);
+        if !rest.is_empty() {
+            formatted.push(' ');
+            formatted.push_str(rest);
+        }
+        formatted.push('\n');
+    }
+    if !is_last {
+        // Indentation of the next entry.
+        let _ = pieces.pop();
+    }
+
+    let mut lines = Vec::with_capacity(pieces.len());
+    let mut prev_blank = is_first;
+    for piece in pieces {
+        if piece.is_empty() && prev_blank {
+            continue;
+        }
+        prev_blank = piece.is_empty();
+        lines.push(piece);
+    }
+    if is_last {
+        while let Some(&"") = lines.last() {
+            let _ = lines.pop();
+        }
+    }
+
+    for line in lines {
+        formatted.push_str(line);
+        formatted.push('\n');
+    }
+    formatted
+}
+
+impl fmt::Display for ConfigDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in self.nodes.iter() {
+            match node {
+                ConfigNode::Trivia(trivia) => write!(f, "{}", trivia)?,
+                ConfigNode::Entry(entry) => {
+                    write!(f, "{}{}{}", entry.name, entry.assign, entry.value)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formatted(config: &str) -> String {
+        let mut document = ConfigDocument::parse(config);
+        document.format();
+        document.to_string()
+    }
+
+    fn set(config: &str, name: &str, val: ConfigVal, profile: Option<&str>) -> String {
+        let mut document = ConfigDocument::parse(config);
+        document.set(&ConfigParam::new(name, val), profile, "# doc");
+        document.to_string()
+    }
+
+    #[test]
+    fn parse_is_lossless() {
+        let config = "# head\n\nspeed  ::  1..3 # fast\n\n\n[profile night]\n  color::(0,0,200)\nbroken line\n";
+        assert_eq!(ConfigDocument::parse(config).to_string(), config);
+    }
+
+    #[test]
+    fn format_normalizes_and_is_idempotent() {
+        let config = "\n\n# head\nspeed  ::  1..3   # fast\n\n\n\n  color::(0,0,200)\n\n\n";
+        let once = formatted(config);
+        assert_eq!(
+            once,
+            "# head\nspeed :: 1..3 # fast\n\ncolor :: (0, 0, 200)\n"
+        );
+        assert_eq!(formatted(&once), once);
+    }
+
+    #[test]
+    fn format_keeps_env_references() {
+        let config = "density::${DENSITY:-0.7}\n";
+        assert_eq!(formatted(config), "density :: ${DENSITY:-0.7}\n");
+    }
+
+    #[test]
+    fn set_updates_in_place_keeping_trailing_comment() {
+        let config = "speed :: 1..3 # fast\nlength :: 5\n";
+        assert_eq!(
+            set(config, "speed", ConfigVal::Num(2.0), None),
+            "speed :: 2 # fast\nlength :: 5\n"
+        );
+    }
+
+    #[test]
+    fn set_keeps_spelling_of_unchanged_values() {
+        let config = "density :: ${DENSITY:-0.7}\n";
+        let val = ConfigParser::new(config)
+            .parse()
+            .unwrap()
+            .unwrap()
+            .split()
+            .1;
+        assert_eq!(set(config, "density", val, None), config);
+    }
+
+    #[test]
+    fn set_inserts_after_last_base_entry() {
+        let config = "speed :: 1 # fast\n\n[profile night]\nspeed :: 2\n";
+        assert_eq!(
+            set(config, "length", ConfigVal::Num(5.0), None),
+            "speed :: 1 # fast\n\n# doc\nlength :: 5\n\n[profile night]\nspeed :: 2\n"
+        );
+    }
+
+    #[test]
+    fn set_inserts_into_empty_document() {
+        assert_eq!(
+            set("", "length", ConfigVal::Num(5.0), None),
+            "# doc\nlength :: 5\n"
+        );
+    }
+
+    #[test]
+    fn set_updates_entry_of_profile() {
+        let config = "speed :: 1\n[profile night]\nspeed :: 2\n";
+        assert_eq!(
+            set(config, "speed", ConfigVal::Num(3.0), Some("night")),
+            "speed :: 1\n[profile night]\nspeed :: 3\n"
+        );
+        assert_eq!(
+            set(config, "speed", ConfigVal::Num(3.0), None),
+            "speed :: 3\n[profile night]\nspeed :: 2\n"
+        );
+    }
+
+    #[test]
+    fn set_inserts_into_profile_section() {
+        let config = "speed :: 1\n[profile night]\nlength :: 5 # long\n[profile day]\n";
+        assert_eq!(
+            set(config, "speed", ConfigVal::Num(3.0), Some("night")),
+            "speed :: 1\n[profile night]\nlength :: 5 # long\n\n# doc\nspeed :: 3\n[profile day]\n"
+        );
+        assert_eq!(
+            set(config, "speed", ConfigVal::Num(3.0), Some("day")),
+            "speed :: 1\n[profile night]\nlength :: 5 # long\n[profile day]\n# doc\nspeed :: 3\n"
+        );
+    }
+
+    #[test]
+    fn set_inserts_under_empty_profile_header() {
+        let config = "speed :: 1\n[ profile  night ]\n# day\n[profile day]\nspeed :: 2\n";
+        assert_eq!(
+            set(config, "speed", ConfigVal::Num(3.0), Some("night")),
+            "speed :: 1\n[ profile  night ]\n# doc\nspeed :: 3\n# day\n[profile day]\nspeed :: 2\n"
+        );
+    }
+
+    #[test]
+    fn set_adds_missing_profile_section() {
+        assert_eq!(
+            set(
+                "speed :: 1 # fast",
+                "speed",
+                ConfigVal::Num(3.0),
+                Some("night")
+            ),
+            "speed :: 1 # fast\n\n[profile night]\n# doc\nspeed :: 3\n"
+        );
+        assert_eq!(
+            set("speed :: 1\n", "speed", ConfigVal::Num(3.0), Some("night")),
+            "speed :: 1\n\n[profile night]\n# doc\nspeed :: 3\n"
+        );
+    }
+
+    #[test]
+    fn doc_is_comment_block_above_entry() {
+        let document = ConfigDocument::parse(concat!(
+            "# head\n",
+            "\n",
+            "# speed\n",
+            "# speed :: 1..2\n",
+            "speed :: 1 # fast\n",
+            "# length\n",
+            "length :: 5\n",
+            "density :: 0.7\n",
+        ));
+        assert_eq!(
+            document.doc("speed").as_deref(),
+            Some("# speed\n# speed :: 1..2")
+        );
+        assert_eq!(document.doc("length").as_deref(), Some("# length"));
+        assert_eq!(document.doc("density").as_deref(), Some(""));
+        assert_eq!(document.doc("delay"), None);
+    }
+
+    #[test]
+    fn get_prefers_selected_profile() {
+        let document = ConfigDocument::parse("speed :: 1\n[profile night]\nspeed :: 2\n");
+        assert_eq!(document.get("speed", None), Some(&ConfigVal::Num(1.0)));
+        assert_eq!(
+            document.get("speed", Some("night")),
+            Some(&ConfigVal::Num(2.0))
+        );
+        assert_eq!(
+            document.get("speed", Some("day")),
+            Some(&ConfigVal::Num(1.0))
+        );
+    }
+}
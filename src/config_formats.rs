@@ -0,0 +1,247 @@
+#![forbid(unsafe_code)]
+
+use crate::config_parser::*;
+
+// Table with exactly these keys is a range (`{ start = 1, end = 3 }`).
+const RANGE_KEYS: [&str; 2] = ["start", "end"];
+// Table of `[profiles.name]` sections.
+const PROFILES_KEY: &str = "profiles";
+
+fn toml_to_config_val(value: toml::Value) -> Result<ConfigVal, String> {
+    Ok(match value {
+        toml::Value::Integer(v) => ConfigVal::Num(v as f32),
+        toml::Value::Float(v) => ConfigVal::Num(v as f32),
+        toml::Value::Boolean(b) => ConfigVal::Bool(b),
+        // TOML has no null value.
+        toml::Value::String(string) if string == "nil" => ConfigVal::Nil,
+        toml::Value::String(string) => ConfigVal::String(string),
+        toml::Value::Array(array) => ConfigVal::Tuple(
+            array
+                .into_iter()
+                .map(toml_to_config_val)
+                .collect::<Result<_, _>>()?,
+        ),
+        toml::Value::Table(mut table)
+            if table.len() == RANGE_KEYS.len()
+                && RANGE_KEYS.iter().all(|&key| table.contains_key(key)) =>
+        {
+            let start = toml_to_config_val(table.remove(RANGE_KEYS[0]).unwrap())?;
+            let end = toml_to_config_val(table.remove(RANGE_KEYS[1]).unwrap())?;
+            ConfigVal::Range(Box::new(start), Box::new(end))
+        }
+        value => return Err(format!("Unexpected value `{}`.", value)),
+    })
+}
+
+fn json_to_config_val(value: serde_json::Value) -> Result<ConfigVal, String> {
+    Ok(match value {
+        serde_json::Value::Number(v) => ConfigVal::Num(v.as_f64().unwrap_or_default() as f32),
+        serde_json::Value::Bool(b) => ConfigVal::Bool(b),
+        serde_json::Value::String(string) => ConfigVal::String(string),
+        serde_json::Value::Null => ConfigVal::Nil,
+        serde_json::Value::Array(array) => ConfigVal::Tuple(
+            array
+                .into_iter()
+                .map(json_to_config_val)
+                .collect::<Result<_, _>>()?,
+        ),
+        serde_json::Value::Object(mut object)
+            if object.len() == RANGE_KEYS.len()
+                && RANGE_KEYS.iter().all(|&key| object.contains_key(key)) =>
+        {
+            let start = json_to_config_val(object.remove(RANGE_KEYS[0]).unwrap())?;
+            let end = json_to_config_val(object.remove(RANGE_KEYS[1]).unwrap())?;
+            ConfigVal::Range(Box::new(start), Box::new(end))
+        }
+        value => return Err(format!("Unexpected value `{}`.", value)),
+    })
+}
+
+/// Parameters of a `config.toml`, in file order.
+pub fn toml_params(slurp_config: &str) -> Vec<Result<ConfigParam, String>> {
+    fn table_params(
+        table: toml::value::Table,
+        profile: Option<&str>,
+        params: &mut Vec<Result<ConfigParam, String>>,
+    ) {
+        for (name, value) in table {
+            match value {
+                toml::Value::Table(profiles) if name == PROFILES_KEY && profile.is_none() => {
+                    for (profile, table) in profiles {
+                        match table {
+                            toml::Value::Table(table) => {
+                                table_params(table, Some(&profile), params)
+                            }
+                            _ => params.push(Err(format!("Profile `{}` is table.", profile))),
+                        }
+                    }
+                }
+                value => params.push(toml_to_config_val(value).map(|val| {
+                    let mut param = ConfigParam::new(name, val);
+                    param.set_profile(profile.map(str::to_string));
+                    param
+                })),
+            }
+        }
+    }
+
+    let mut params = Vec::new();
+    match toml::from_str(slurp_config) {
+        Ok(table) => table_params(table, None, &mut params),
+        Err(err) => params.push(Err(format!("{}\n", err))),
+    }
+    params
+}
+
+/// Parameters of a `config.json`, in file order.
+pub fn json_params(slurp_config: &str) -> Vec<Result<ConfigParam, String>> {
+    fn object_params(
+        object: serde_json::Map<String, serde_json::Value>,
+        profile: Option<&str>,
+        params: &mut Vec<Result<ConfigParam, String>>,
+    ) {
+        for (name, value) in object {
+            match value {
+                serde_json::Value::Object(profiles)
+                    if name == PROFILES_KEY && profile.is_none() =>
+                {
+                    for (profile, object) in profiles {
+                        match object {
+                            serde_json::Value::Object(object) => {
+                                object_params(object, Some(&profile), params)
+                            }
+                            _ => params.push(Err(format!("Profile `{}` is object.", profile))),
+                        }
+                    }
+                }
+                value => params.push(json_to_config_val(value).map(|val| {
+                    let mut param = ConfigParam::new(name, val);
+                    param.set_profile(profile.map(str::to_string));
+                    param
+                })),
+            }
+        }
+    }
+
+    let mut params = Vec::new();
+    match serde_json::from_str(slurp_config) {
+        Ok(object) => object_params(object, None, &mut params),
+        Err(err) => params.push(Err(format!("{}\n", err))),
+    }
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(
+        params: Vec<Result<ConfigParam, String>>,
+    ) -> Vec<(String, Option<String>, ConfigVal)> {
+        params
+            .into_iter()
+            .map(|param| {
+                let param = param.unwrap();
+                let profile = param.profile().map(str::to_string);
+                let (name, val) = param.split();
+                (name, profile, val)
+            })
+            .collect()
+    }
+
+    fn color(r: f32, g: f32, b: f32) -> ConfigVal {
+        ConfigVal::Tuple(vec![
+            ConfigVal::Num(r),
+            ConfigVal::Num(g),
+            ConfigVal::Num(b),
+        ])
+    }
+
+    fn range(start: ConfigVal, end: ConfigVal) -> ConfigVal {
+        ConfigVal::Range(Box::new(start), Box::new(end))
+    }
+
+    #[test]
+    fn toml_ranges_nil_and_profiles() {
+        let params = toml_params(concat!(
+            "speed = { start = 60, end = 180 }\n",
+            "color = { start = [0, 0, 0], end = [0, 255, 0] }\n",
+            "head_color = \"nil\"\n",
+            "utf8 = true\n",
+            "[profiles.night]\n",
+            "color = [0, 0, 200]\n",
+        ));
+        assert_eq!(
+            summary(params),
+            vec![
+                (
+                    "speed".to_string(),
+                    None,
+                    range(ConfigVal::Num(60.0), ConfigVal::Num(180.0))
+                ),
+                (
+                    "color".to_string(),
+                    None,
+                    range(color(0.0, 0.0, 0.0), color(0.0, 255.0, 0.0))
+                ),
+                ("head_color".to_string(), None, ConfigVal::Nil),
+                ("utf8".to_string(), None, ConfigVal::Bool(true)),
+                (
+                    "color".to_string(),
+                    Some("night".to_string()),
+                    color(0.0, 0.0, 200.0)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn json_ranges_null_and_profiles() {
+        let params = json_params(
+            r#"{
+                "speed": { "start": 60, "end": 180 },
+                "head_color": null,
+                "direction": "down",
+                "profiles": { "night": { "color": [0, 0, 200] } }
+            }"#,
+        );
+        assert_eq!(
+            summary(params),
+            vec![
+                (
+                    "speed".to_string(),
+                    None,
+                    range(ConfigVal::Num(60.0), ConfigVal::Num(180.0))
+                ),
+                ("head_color".to_string(), None, ConfigVal::Nil),
+                (
+                    "direction".to_string(),
+                    None,
+                    ConfigVal::String("down".to_string())
+                ),
+                (
+                    "color".to_string(),
+                    Some("night".to_string()),
+                    color(0.0, 0.0, 200.0)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn tables_other_than_ranges_are_errors() {
+        let params = toml_params("wind = { x = 1, y = 2 }\n[profiles]\nnight = 3\n");
+        assert_eq!(params.len(), 2);
+        assert!(params.iter().all(Result::is_err));
+
+        let params = json_params(r#"{ "wind": { "start": 1 }, "profiles": { "night": 3 } }"#);
+        assert_eq!(params.len(), 2);
+        assert!(params.iter().all(Result::is_err));
+    }
+
+    #[test]
+    fn syntax_errors_are_reported() {
+        assert!(toml_params("speed = ").iter().all(Result::is_err));
+        assert!(json_params("{ \"speed\": ").iter().all(Result::is_err));
+    }
+}
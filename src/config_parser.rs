@@ -2,6 +2,7 @@
 
 use core::fmt;
 use core::iter::Peekable;
+use core::ops::Range;
 
 #[derive(Debug, Clone)]
 pub struct ConfigParser<'s, I>
@@ -14,6 +15,9 @@ where
 
     line: usize,
     line_to_idx: usize,
+
+    // Name from the last `[profile name]` header.
+    profile: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -31,7 +35,7 @@ impl fmt::Display for ConfigErr<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "{}", self.msg)?;
 
-        let current_idx = self.idx.unwrap_or_else(|| self.slurp_config.len());
+        let current_idx = self.idx.unwrap_or(self.slurp_config.len());
         let end = self.slurp_config[current_idx..]
             .char_indices()
             .find(|&(_, ch)| ch == '\n')
@@ -46,7 +50,7 @@ impl fmt::Display for ConfigErr<'_> {
             write!(f, " ")?;
         }
 
-        let err_line = if let Some(_) = self.idx {
+        let err_line = if self.idx.is_some() {
             &self.slurp_config[self.line_to_idx..=current_idx]
         } else {
             &self.slurp_config[self.line_to_idx..current_idx]
@@ -65,15 +69,48 @@ impl fmt::Display for ConfigErr<'_> {
 pub struct ConfigParam {
     name: String,
     val: ConfigVal,
+    profile: Option<String>,
 }
 
 impl ConfigParam {
+    pub fn new<S: Into<String>>(name: S, val: ConfigVal) -> Self {
+        Self {
+            name: name.into(),
+            val,
+            profile: None,
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn val(&self) -> &ConfigVal {
+        &self.val
+    }
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+    pub fn set_profile(&mut self, profile: Option<String>) {
+        self.profile = profile;
+    }
     pub fn split(self) -> (String, ConfigVal) {
         (self.name, self.val)
     }
 }
 
+impl fmt::Display for ConfigParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} :: {}", self.name, self.val)
+    }
+}
+
+/// Byte ranges of a parsed parameter inside the config source.
 #[derive(Debug, Clone)]
+pub struct ConfigSpan {
+    pub name: Range<usize>,
+    pub value: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ConfigVal {
     Num(f32),
     Tuple(Vec<ConfigVal>),
@@ -83,6 +120,28 @@ pub enum ConfigVal {
     Nil,
 }
 
+impl fmt::Display for ConfigVal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigVal::Num(v) => write!(f, "{}", v),
+            ConfigVal::Tuple(tuple) => {
+                write!(f, "(")?;
+                for (idx, val) in tuple.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", val)?;
+                }
+                write!(f, ")")
+            }
+            ConfigVal::Range(start, end) => write!(f, "{}..{}", start, end),
+            ConfigVal::Bool(b) => write!(f, "{}", b),
+            ConfigVal::String(string) => write!(f, "\"{}\"", string),
+            ConfigVal::Nil => write!(f, "nil"),
+        }
+    }
+}
+
 impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
     pub fn new(slurp_config: &'s str) -> Self {
         Self {
@@ -90,21 +149,44 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
             iter: slurp_config.char_indices().peekable(),
             line: 1,
             line_to_idx: 0,
+            profile: None,
         }
     }
 
     fn make_err(&self, msg: String, idx: Option<usize>) -> ConfigErr<'s> {
         ConfigErr {
-            msg: msg,
+            msg,
             slurp_config: self.slurp_config,
             line: self.line,
             line_to_idx: self.line_to_idx,
-            idx: idx,
+            idx,
+        }
+    }
+
+    // Error at `idx` which may lie before the current line.
+    fn make_err_at(&self, msg: &str, idx: usize) -> ConfigErr<'s> {
+        let line = 1 + self.slurp_config[..idx].matches('\n').count();
+        let line_to_idx = self.slurp_config[..idx]
+            .rfind('\n')
+            .map(|new_line| new_line + 1)
+            .unwrap_or(0);
+
+        ConfigErr {
+            msg: format!(
+                "error on line: {}, column: {}. {}",
+                line,
+                idx - line_to_idx,
+                msg
+            ),
+            slurp_config: self.slurp_config,
+            line,
+            line_to_idx,
+            idx: Some(idx),
         }
     }
 
     fn move_to_new_line(&mut self) {
-        while let Some((idx, ch)) = self.iter.next() {
+        for (idx, ch) in self.iter.by_ref() {
             if ch == '\n' {
                 self.line += 1;
                 self.line_to_idx = idx + 1;
@@ -197,6 +279,7 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
                     || ch == '.'
                     || ch == ','
                     || ch == ';'
+                    || ch == ']'
                     || ch == '\"'
                 {
                     if start == end {
@@ -221,16 +304,22 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
         let err = format!(
             "error on line: {}, column: {}. Expected `variable name`.",
             self.line,
-            idx.unwrap_or_else(|| self.slurp_config.len()) - self.line_to_idx
+            idx.unwrap_or(self.slurp_config.len()) - self.line_to_idx
         );
         Err(self.make_err(err, idx))
     }
 
     fn parse_num(&mut self) -> ConfigVal {
-        if let Some(&(start, _)) = self.iter.peek() {
+        if let Some(&(start, ch)) = self.iter.peek() {
+            if ch == '-' {
+                let _ = self.iter.next();
+            }
+
             let mut dot_cnt = 0;
             while let Some(&(end, ch)) = self.iter.peek() {
-                if (!ch.is_digit(10) && ch != '.') || (ch == '.' && dot_cnt > 0) || self.maybe("..")
+                if (!ch.is_ascii_digit() && ch != '.')
+                    || (ch == '.' && dot_cnt > 0)
+                    || self.maybe("..")
                 {
                     let num = &self.slurp_config[start..end];
                     return ConfigVal::Num(num.parse().unwrap_or_default());
@@ -246,6 +335,10 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
                 }
                 let _ = self.iter.next();
             }
+
+            // The number runs up to the end of the config.
+            let num = &self.slurp_config[start..];
+            return ConfigVal::Num(num.parse().unwrap_or_default());
         }
         ConfigVal::Num(Default::default())
     }
@@ -253,7 +346,7 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
     fn parse_tuple(&mut self) -> Result<ConfigVal, ConfigErr<'s>> {
         let mut tuple = Vec::with_capacity(3);
 
-        let _ = self.need("(")?;
+        self.need("(")?;
         loop {
             self.skip(char::is_whitespace);
 
@@ -276,7 +369,7 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
                 let err = format!(
                     "error on line: {}, column: {}. Expected `,` or `)`.",
                     self.line,
-                    idx.unwrap_or_else(|| self.slurp_config.len()) - self.line_to_idx
+                    idx.unwrap_or(self.slurp_config.len()) - self.line_to_idx
                 );
                 return Err(self.make_err(err, idx));
             }
@@ -286,7 +379,7 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
 
     fn parse_value(&mut self) -> Result<ConfigVal, ConfigErr<'s>> {
         if let Some(&(idx, ch)) = self.iter.peek() {
-            let value = if ch.is_digit(10) || ch == '.' {
+            let value = if ch.is_ascii_digit() || ch == '.' || ch == '-' {
                 let num = self.parse_num();
 
                 self.skip(char::is_whitespace);
@@ -295,7 +388,11 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
                     let _ = self.iter.next();
 
                     self.skip(char::is_whitespace);
-                    let num_last = self.parse_num();
+                    let num_last = if self.maybe("${") {
+                        self.parse_env_value()?
+                    } else {
+                        self.parse_num()
+                    };
 
                     ConfigVal::Range(Box::new(num), Box::new(num_last))
                 } else {
@@ -316,6 +413,21 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
                 } else {
                     tuple
                 }
+            } else if ch == '$' {
+                let val = self.parse_env_value()?;
+
+                self.skip(char::is_whitespace);
+                if self.maybe("..") {
+                    let _ = self.iter.next();
+                    let _ = self.iter.next();
+
+                    self.skip(char::is_whitespace);
+                    let val_last = self.parse_value()?;
+
+                    ConfigVal::Range(Box::new(val), Box::new(val_last))
+                } else {
+                    val
+                }
             } else if self.maybe("true") {
                 self.skip_n("true".len());
                 ConfigVal::Bool(true)
@@ -331,7 +443,7 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
 
                 self.skip(|ch| ch != '\"');
                 if let Some((idx, _)) = self.iter.next() {
-                    ConfigVal::String(self.slurp_config[start..idx].to_string())
+                    ConfigVal::String(self.expand_env_string(start, idx)?)
                 } else {
                     let err = format!("error on line: {}. Expected end of string \".", self.line);
                     return Err(self.make_err(err, None));
@@ -360,12 +472,114 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
             Err(self.make_err(err, idx))
         }
     }
-    pub fn parse(&mut self) -> Option<Result<ConfigParam, ConfigErr>> {
+    // Value of `NAME` or `NAME:-default` from a `${...}` reference at `idx`.
+    fn expand_env(&self, reference: &str, idx: usize) -> Result<String, ConfigErr<'s>> {
+        let (name, default) = match reference.find(":-") {
+            Some(split) => (&reference[..split], Some(&reference[split + 2..])),
+            None => (reference, None),
+        };
+
+        match (std::env::var(name), default) {
+            (Ok(val), None) => Ok(val),
+            (Ok(val), Some(_)) if !val.is_empty() => Ok(val),
+            (_, Some(default)) => Ok(default.to_string()),
+            (Err(_), None) => {
+                Err(self.make_err_at(&format!("Environment variable `{}` is not set.", name), idx))
+            }
+        }
+    }
+
+    fn expand_env_string(&self, start: usize, end: usize) -> Result<String, ConfigErr<'s>> {
+        let slurp_config = self.slurp_config;
+        let mut string = String::with_capacity(end - start);
+
+        let mut last = start;
+        while let Some(offset) = slurp_config[last..end].find("${") {
+            let reference_start = last + offset;
+            let reference_end = match slurp_config[reference_start..end].find('}') {
+                Some(close) => reference_start + close,
+                None => return Err(self.make_err_at("Expected `}`.", end)),
+            };
+
+            string.push_str(&slurp_config[last..reference_start]);
+            string.push_str(&self.expand_env(
+                &slurp_config[reference_start + 2..reference_end],
+                reference_start,
+            )?);
+            last = reference_end + 1;
+        }
+        string.push_str(&slurp_config[last..end]);
+
+        Ok(string)
+    }
+
+    fn parse_env_value(&mut self) -> Result<ConfigVal, ConfigErr<'s>> {
+        let slurp_config = self.slurp_config;
+        let start = self.pos();
+
+        self.need("${")?;
+        let reference_start = self.pos();
+        self.skip(|ch| ch != '}' && ch != '\n');
+        let reference_end = self.pos();
+        self.need("}")?;
+
+        let reference = &slurp_config[reference_start..reference_end];
+        let expanded = self.expand_env(reference, start)?;
+
+        let mut parser = ConfigParser::new(&expanded);
+        let val = parser.parse_value().ok().filter(|_| {
+            parser.skip(char::is_whitespace);
+            parser.pos() == expanded.len()
+        });
+
+        val.ok_or_else(|| {
+            self.make_err_at(
+                &format!(
+                    "`${{{}}}` expands to `{}` which is not a value.",
+                    reference, expanded
+                ),
+                start,
+            )
+        })
+    }
+
+    fn parse_section(&mut self) -> Result<String, ConfigErr<'s>> {
+        self.need("[")?;
+        self.skip(char::is_whitespace);
+        self.need("profile")?;
+        self.skip(char::is_whitespace);
+        let profile = self.parse_variable()?;
+        self.skip(char::is_whitespace);
+        self.need("]")?;
+        Ok(profile)
+    }
+
+    fn pos(&mut self) -> usize {
+        if let Some(&(idx, _)) = self.iter.peek() {
+            idx
+        } else {
+            self.slurp_config.len()
+        }
+    }
+
+    pub fn parse(&mut self) -> Option<Result<ConfigParam, ConfigErr<'s>>> {
+        self.parse_spanned().map(|res| res.map(|(param, _)| param))
+    }
+
+    pub fn parse_spanned(&mut self) -> Option<Result<(ConfigParam, ConfigSpan), ConfigErr<'s>>> {
         loop {
             self.skip(char::is_whitespace);
             match self.iter.peek() {
                 Some((_, '#')) => self.move_to_new_line(),
+                Some((_, '[')) => match self.parse_section() {
+                    Ok(profile) => self.profile = Some(profile),
+                    Err(section_parse_err) => {
+                        self.move_to_new_line();
+                        return Some(Err(section_parse_err));
+                    }
+                },
                 Some(_) => {
+                    let name_start = self.pos();
                     let variable = self.parse_variable();
 
                     let variable_name = match variable {
@@ -375,6 +589,7 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
                             return Some(Err(variable_parse_err));
                         }
                     };
+                    let name_end = self.pos();
 
                     self.skip(char::is_whitespace);
                     let is_assign = self.need("::");
@@ -385,11 +600,26 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
                     }
 
                     self.skip(char::is_whitespace);
+                    let value_start = self.pos();
                     return Some(match self.parse_value() {
-                        Ok(config_parse_val) => Ok(ConfigParam {
-                            name: variable_name,
-                            val: config_parse_val,
-                        }),
+                        Ok(config_parse_val) => {
+                            // `parse_value` may look ahead over whitespace for `..`.
+                            let value_end = self.pos();
+                            let value_end = value_start
+                                + self.slurp_config[value_start..value_end].trim_end().len();
+
+                            Ok((
+                                ConfigParam {
+                                    name: variable_name,
+                                    val: config_parse_val,
+                                    profile: self.profile.clone(),
+                                },
+                                ConfigSpan {
+                                    name: name_start..name_end,
+                                    value: value_start..value_end,
+                                },
+                            ))
+                        }
                         Err(config_parse_err) => {
                             self.move_to_new_line();
                             Err(config_parse_err)
@@ -401,3 +631,86 @@ impl<'s> ConfigParser<'s, core::str::CharIndices<'s>> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_val(config: &str) -> Result<ConfigVal, String> {
+        ConfigParser::new(config)
+            .parse()
+            .unwrap()
+            .map(|param| param.split().1)
+            .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn env_default_inside_tuple() {
+        assert_eq!(
+            parse_val("color :: (0, ${RSMATRIX_TEST_UNSET_GREEN:-200}, 0)"),
+            Ok(ConfigVal::Tuple(vec![
+                ConfigVal::Num(0.0),
+                ConfigVal::Num(200.0),
+                ConfigVal::Num(0.0),
+            ]))
+        );
+    }
+
+    #[test]
+    fn env_default_inside_range() {
+        assert_eq!(
+            parse_val("speed :: ${RSMATRIX_TEST_UNSET_MIN:-60}..${RSMATRIX_TEST_UNSET_MAX:-180}"),
+            Ok(ConfigVal::Range(
+                Box::new(ConfigVal::Num(60.0)),
+                Box::new(ConfigVal::Num(180.0)),
+            ))
+        );
+    }
+
+    #[test]
+    fn env_value_overrides_default() {
+        std::env::set_var("RSMATRIX_TEST_DENSITY", "0.3");
+        std::env::set_var("RSMATRIX_TEST_EMPTY", "");
+        assert_eq!(
+            parse_val("density :: ${RSMATRIX_TEST_DENSITY:-0.7}"),
+            Ok(ConfigVal::Num(0.3))
+        );
+        assert_eq!(
+            parse_val("density :: ${RSMATRIX_TEST_EMPTY:-0.7}"),
+            Ok(ConfigVal::Num(0.7))
+        );
+    }
+
+    #[test]
+    fn env_inside_string() {
+        std::env::set_var("RSMATRIX_TEST_DIR", "/tmp");
+        assert_eq!(
+            parse_val("error_report_file :: \"${RSMATRIX_TEST_DIR}/${RSMATRIX_TEST_UNSET_NAME:-err}.txt\""),
+            Ok(ConfigVal::String("/tmp/err.txt".to_string()))
+        );
+    }
+
+    #[test]
+    fn missing_env_points_at_reference() {
+        let err = parse_val("density :: ${RSMATRIX_TEST_MISSING}").unwrap_err();
+        let lines = err.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "error on line: 1, column: 11. Environment variable `RSMATRIX_TEST_MISSING` is not set."
+        );
+        assert_eq!(lines[1], "1 | density :: ${RSMATRIX_TEST_MISSING}");
+        // The carets end under the `$` of the reference.
+        assert_eq!(
+            lines[2],
+            format!("    {}", "^".repeat("density :: $".len()))
+        );
+    }
+
+    #[test]
+    fn env_expanding_to_no_value_is_error() {
+        std::env::set_var("RSMATRIX_TEST_WORD", "fast");
+        assert!(parse_val("density :: ${RSMATRIX_TEST_WORD}")
+            .unwrap_err()
+            .contains("`${RSMATRIX_TEST_WORD}` expands to `fast` which is not a value."));
+    }
+}
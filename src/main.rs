@@ -3,6 +3,7 @@
 use crossterm::{cursor, event, style, terminal, ExecutableCommand, QueueableCommand};
 
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::{fs, io, time};
 
@@ -10,13 +11,63 @@ use notify::{
     event::ModifyKind, Error, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
 };
 
-use rand::prelude::*;
-
+mod config_document;
+mod config_formats;
 mod config_parser;
+mod noise;
+mod pacing;
 mod rmatrix;
+mod sync_output;
+mod themes;
 
+use config_document::*;
+use config_formats::*;
 use config_parser::*;
+use pacing::*;
 use rmatrix::*;
+use sync_output::*;
+
+// Rough size of a moved, colored glyph, used to pick the color depth.
+const TRUE_COLOR_CELL_BYTES: usize = 32;
+// Priority gained by a deferred update per frame it waits, so dim updates
+// catch up with heads (+256) and bright cells (+255) eventually.
+const DEFERRED_PRIORITY: u16 = 32;
+
+/// Counts the bytes that actually reach the terminal.
+struct ByteCounter<'w, W: Write> {
+    out: &'w mut W,
+    bytes: usize,
+}
+
+impl<W: Write> Write for ByteCounter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.out.write(buf)?;
+        self.bytes += len;
+        Ok(len)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// Nearest color of the xterm 6x6x6 color cube and its value.
+fn ansi_256(color: (u8, u8, u8)) -> (u8, (u8, u8, u8)) {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let level = |c: u8| {
+        LEVELS
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &level)| (level as i16 - c as i16).abs())
+            .map(|(idx, _)| idx as u8)
+            .unwrap_or_default()
+    };
+
+    let (r, g, b) = (level(color.0), level(color.1), level(color.2));
+    (
+        16 + 36 * r + 6 * g + b,
+        (LEVELS[r as usize], LEVELS[g as usize], LEVELS[b as usize]),
+    )
+}
 
 #[derive(Debug)]
 struct RmatrixCrosstermRender<'rm> {
@@ -24,145 +75,270 @@ struct RmatrixCrosstermRender<'rm> {
 }
 
 impl<'rm> RmatrixCrosstermRender<'rm> {
-    fn render<O>(&mut self, out: &mut O) -> crossterm::Result<()>
-    where
-        O: Write + QueueableCommand,
-    {
-        let need_double_buffer = !self.rmatrix.is_default_rain
-            || self.rmatrix.interpolate_color_koef.is_some()
-            || self.rmatrix.start_gradient_color.is_some();
-        let mut double_buffer = if need_double_buffer {
-            Some(vec![
-                0u8;
-                self.rmatrix.width as usize
-                    * (self.rmatrix.height as usize + 1)
-            ])
-        } else {
-            None
-        };
+    // The frame that should be on the terminal; rains reaching the same cell
+    // are combined with `rmatrix.blend`.
+    fn compose(&mut self) -> Vec<Cell> {
+        let width = self.rmatrix.width as usize;
+        let height = self.rmatrix.height;
+        let mut frame = vec![Cell::BLANK; width * height as usize];
+        let mut is_drawn = vec![false; frame.len()];
 
-        if self.rmatrix.is_bold {
-            out.queue(style::SetAttribute(style::Attribute::Bold))?;
-        }
+        let gradient = self.rmatrix.gradient.clone().unwrap_or_else(|| {
+            let start_color = self
+                .rmatrix
+                .start_gradient_color
+                .clone()
+                .unwrap_or_default();
+            Gradient(vec![(0.0, start_color), (1.0, self.rmatrix.color.clone())])
+        });
+        let space = self.rmatrix.color_space;
+        let curve = self.rmatrix.curve.as_ref();
+        let is_gradient = self.rmatrix.interpolate_color_koef.is_some()
+            || self.rmatrix.start_gradient_color.is_some()
+            || self.rmatrix.gradient.is_some();
 
-        let start_color = self
-            .rmatrix
-            .start_gradient_color
-            .clone()
-            .unwrap_or_default();
-        let color = self.rmatrix.color.sub(&start_color);
+        let blend = self.rmatrix.blend;
+        // The cell that shows among the ones drawn on each cell so far.
+        let mut tops = vec![Cell::BLANK; frame.len()];
+        let mut put = |frame: &mut Vec<Cell>, idx: usize, cell: Cell| {
+            if is_drawn[idx] {
+                frame[idx] = blend.blend(frame[idx], tops[idx], cell);
+                tops[idx] = cell.top(tops[idx]);
+            } else {
+                frame[idx] = cell;
+                tops[idx] = cell;
+            }
+            is_drawn[idx] = true;
+        };
+        let cell_idx = |(x, y): (i32, i32)| {
+            if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+                None
+            } else {
+                Some(y as usize * width + x as usize)
+            }
+        };
+        let symbl_at = |head: &Rain, steps: usize, idx: usize| {
+            let symbls = &self.rmatrix.symbls;
+            if self.rmatrix.is_glyph_grid {
+                self.rmatrix.glyphs[idx]
+            } else if self.rmatrix.is_default_rain {
+                // Glyphs stay on their cells while the rain moves over them.
+                let pos = head.symbl_pos as i64 + head.entered - steps as i64;
+                symbls[pos.rem_euclid(symbls.len() as i64) as usize]
+            } else {
+                head.glyphs[head.glyphs.len() - 1 - steps]
+            }
+        };
 
         for head in self.rmatrix.rains.iter() {
-            let start_y = head.y.saturating_sub(head.length);
+            let brightnes = head.brightnes.unwrap_or(1.0);
+            let interpolate_koef = self.rmatrix.interpolate_color_koef.unwrap_or(1.0);
+            let rain_gradient;
+            let gradient = match &head.color {
+                Some(color) => {
+                    rain_gradient = gradient.with_end(color);
+                    &rain_gradient
+                }
+                None => &gradient,
+            };
+            let head_color = head
+                .head_color
+                .clone()
+                .or_else(|| self.rmatrix.head_color.clone())
+                .unwrap_or_else(|| gradient.at(1.0, space).scale(brightnes, space));
 
-            let need_y = start_y.saturating_sub(head.speed).saturating_sub(1);
-            for y in need_y..start_y {
-                out.queue(cursor::MoveTo(head.x, y))?
-                    .queue(style::Print(' '))?;
+            let head_steps = head.trail.len() - 1;
+            for (pos, &cell) in head.trail.iter().enumerate() {
+                let steps = head_steps - pos;
+                let idx = match cell_idx(cell) {
+                    Some(idx) => idx,
+                    None => continue,
+                };
+
+                let mut cell_color = if steps < self.rmatrix.head_cells as usize {
+                    head_color.clone()
+                } else if is_gradient {
+                    let t = (head.length as usize - steps) as f32 / head.length as f32;
+                    let walked_len = interpolate_koef * curve.map_or(t, |curve| curve.at(t));
+                    gradient.at(walked_len, space).scale(brightnes, space)
+                } else {
+                    let t = (head.length as usize - steps) as f32 / head.length as f32;
+                    let fade = curve.map_or(1.0, |curve| curve.at(t));
+                    gradient.at(1.0, space).scale(brightnes * fade, space)
+                };
+                if let Some(shift) = self.rmatrix.hue_shift(head, cell, steps) {
+                    cell_color = cell_color.shift_hue(shift, self.rmatrix.hue_saturation);
+                }
+                put(
+                    &mut frame,
+                    idx,
+                    Cell {
+                        symbl: symbl_at(head, steps, idx),
+                        color: cell_color.tuple(),
+                        is_head: steps == 0,
+                    },
+                );
             }
+        }
 
-            let (start_color, color) = if let Some(brightnes) = head.brightnes {
-                (
-                    start_color.interpolate(brightnes),
-                    color.interpolate(brightnes),
-                )
-            } else {
-                (start_color.clone(), color.clone())
-            };
+        if let Some(decay_time) = self.rmatrix.decay_time {
+            let time = self.rmatrix.time;
+            for (idx, glow) in self.rmatrix.glow.iter_mut().enumerate() {
+                let faded = glow.faded(time, decay_time, space);
+                if !is_drawn[idx] {
+                    if let Some(mut faded) = faded {
+                        if self.rmatrix.is_glyph_grid {
+                            faded.symbl = self.rmatrix.glyphs[idx];
+                        }
+                        frame[idx] = faded;
+                    }
+                    continue;
+                }
 
-            if self.rmatrix.interpolate_color_koef.is_none()
-                && self.rmatrix.start_gradient_color.is_none()
-            {
-                out.queue(style::SetForegroundColor(color.tuple().into()))?;
+                // A cell glows with the brightest trail that passed it lately.
+                let cell = frame[idx];
+                glow.symbl = cell.symbl;
+                if !cell.is_head && cell.brightnes() >= faded.map_or(0, |faded| faded.brightnes()) {
+                    glow.color = cell.color;
+                    glow.lit_at = time;
+                }
             }
+        }
 
-            if let Some(double_buffer) = double_buffer.as_mut() {
-                let mut double_buffer_idx =
-                    start_y as usize * self.rmatrix.width as usize + head.x as usize;
+        frame
+    }
 
-                let interpolate_koef = self.rmatrix.interpolate_color_koef.unwrap_or(1.0);
+    /// Draw the cells that changed since the last frame and return the number
+    /// of bytes written. Over `max_bytes_per_frame` heads and bright cells go
+    /// first with fewer colors and the rest waits for the next frames.
+    fn render<O>(&mut self, out: &mut O) -> crossterm::Result<usize>
+    where
+        O: Write + QueueableCommand,
+    {
+        let frame = self.compose();
+        let screen = &mut self.rmatrix.screen;
+        let deferred = &mut self.rmatrix.deferred;
+        let width = self.rmatrix.width as usize;
 
-                let ddc = 1.0 / head.length as f32 * interpolate_koef;
-                let not_visible_len = head.length - (head.y - start_y);
-                let mut walked_len = ddc * not_visible_len as f32;
-                for y in start_y..head.y {
-                    if y > self.rmatrix.height {
-                        break;
-                    }
+        let mut changed = (0..frame.len())
+            .filter(|&idx| !frame[idx].looks_like(&screen[idx]))
+            .collect::<Vec<_>>();
 
-                    let symbl = if self.rmatrix.is_default_rain {
-                        let pos =
-                            (head.symbl_pos as usize + y as usize) % self.rmatrix.symbls.len();
-                        self.rmatrix.symbls[pos]
-                    } else {
-                        *self
-                            .rmatrix
-                            .symbls
-                            .choose(&mut self.rmatrix.rng)
-                            .expect("Invalid choose")
-                    };
-
-                    if self.rmatrix.interpolate_color_koef.is_some()
-                        || self.rmatrix.start_gradient_color.is_some()
-                    {
-                        let dc = walked_len;
-                        walked_len += ddc;
+        let budget = self.rmatrix.max_bytes_per_frame.unwrap_or(usize::MAX);
+        let is_over_budget = changed.len().saturating_mul(TRUE_COLOR_CELL_BYTES) > budget;
+        if is_over_budget {
+            // Cells already drawn in the fewer colors are not sent again.
+            changed.retain(|&idx| {
+                let cell = Cell {
+                    color: ansi_256(frame[idx].color).1,
+                    ..frame[idx]
+                };
+                !cell.looks_like(&screen[idx])
+            });
+            changed.sort_by_key(|&idx| {
+                let cell = &frame[idx];
+                core::cmp::Reverse(
+                    cell.is_head as u16 * 256
+                        + cell.brightnes() as u16
+                        + deferred[idx] as u16 * DEFERRED_PRIORITY,
+                )
+            });
+        }
 
-                        let color = start_color.add(&color.interpolate(dc));
-                        out.queue(style::SetForegroundColor(color.tuple().into()))?;
-                    }
+        let mut out = ByteCounter { out, bytes: 0 };
+        let mut cell_out = Vec::with_capacity(TRUE_COLOR_CELL_BYTES);
+        let mut cursor = None;
+        let mut current_color = None;
 
-                    if double_buffer[double_buffer_idx] == 0 {
-                        double_buffer[double_buffer_idx] = 1;
-                        out.queue(cursor::MoveTo(head.x, y))?
-                            .queue(style::Print(symbl))?;
-                    }
-                    double_buffer_idx += self.rmatrix.width as usize;
-                }
-            } else {
-                let need_y = head.y.saturating_sub(head.speed);
-                let last_y = head.y.min(self.rmatrix.height);
-                for y in need_y..=last_y {
-                    let pos = (head.symbl_pos as usize + y as usize) % self.rmatrix.symbls.len();
-                    let symbl = self.rmatrix.symbls[pos];
+        if !changed.is_empty() && self.rmatrix.is_bold {
+            out.queue(style::SetAttribute(style::Attribute::Bold))?;
+        }
 
-                    out.queue(cursor::MoveTo(head.x, y))?
-                        .queue(style::Print(symbl))?;
-                }
-            }
+        for (pos, &idx) in changed.iter().enumerate() {
+            let mut cell = frame[idx];
+            let (x, y) = ((idx % width) as u16, (idx / width) as u16);
 
-            if head.y > self.rmatrix.height {
-                continue;
+            cell_out.clear();
+            if cursor != Some((x, y)) {
+                cell_out.queue(cursor::MoveTo(x, y))?;
             }
-
-            let double_buffer_idx = head.y as usize * self.rmatrix.width as usize + head.x as usize;
-            if let Some(double_buffer) = double_buffer.as_mut() {
-                if double_buffer[double_buffer_idx] == 0 {
-                    double_buffer[double_buffer_idx] = 1;
+            if cell.symbl != ' ' && current_color != Some(cell.color) {
+                if is_over_budget {
+                    let (ansi, color) = ansi_256(cell.color);
+                    cell.color = color;
+                    cell_out.queue(style::SetForegroundColor(style::Color::AnsiValue(ansi)))?;
                 } else {
-                    continue;
+                    cell_out.queue(style::SetForegroundColor(cell.color.into()))?;
                 }
             }
+            cell_out.queue(style::Print(cell.symbl))?;
 
-            let head_symbl = if self.rmatrix.is_default_rain {
-                let pos = (head.symbl_pos as usize + head.y as usize) % self.rmatrix.symbls.len();
-                self.rmatrix.symbls[pos]
-            } else {
-                self.rmatrix.symbls[head.symbl_pos as usize]
-            };
+            if out.bytes + cell_out.len() > budget {
+                // The rest stays stale on the screen and is retried later.
+                for &idx in changed[pos..].iter() {
+                    deferred[idx] = deferred[idx].saturating_add(1);
+                }
+                break;
+            }
+            out.write_all(&cell_out)?;
 
-            if let Some(head_color) = self.rmatrix.head_color.clone() {
-                out.queue(style::SetForegroundColor(head_color.tuple().into()))?;
+            if cell.symbl != ' ' {
+                current_color = Some(cell.color);
             }
-            out.queue(cursor::MoveTo(head.x, head.y))?
-                .queue(style::Print(head_symbl))?;
+            cursor = Some((x + 1, y));
+            screen[idx] = cell;
+            deferred[idx] = 0;
         }
 
         out.flush()?;
-        Ok(())
+        Ok(out.bytes)
     }
 }
 
+fn render_stats<O>(out: &mut O, pacer: &FramePacer, rmatrix: &mut Rmatrix) -> crossterm::Result<()>
+where
+    O: Write + QueueableCommand,
+{
+    let stats = format!(
+        " {:.1} fps | render {:.1} ms | {} B/frame | quality {:.0}% | rains {} ",
+        pacer.fps(),
+        pacer.render_time().as_secs_f32() * 1000.0,
+        pacer.bytes(),
+        rmatrix.quality * 100.0,
+        rmatrix.rains.len(),
+    );
+    render_line(out, 0, &stats, rmatrix)
+}
+
+// One line of feedback at the bottom of the screen, e.g. after saving.
+fn render_status<O>(out: &mut O, status: &str, rmatrix: &mut Rmatrix) -> crossterm::Result<()>
+where
+    O: Write + QueueableCommand,
+{
+    let row = rmatrix.height.saturating_sub(1);
+    render_line(out, row, &format!(" {} ", status), rmatrix)
+}
+
+// Print `text` over the rains at the start of `row`.
+fn render_line<O>(out: &mut O, row: u16, text: &str, rmatrix: &mut Rmatrix) -> crossterm::Result<()>
+where
+    O: Write + QueueableCommand,
+{
+    let text = text
+        .chars()
+        .take(rmatrix.width as usize)
+        .collect::<String>();
+
+    out.queue(cursor::MoveTo(0, row))?
+        .queue(style::SetForegroundColor(style::Color::White))?
+        .queue(style::Print(&text))?;
+    out.flush()?;
+
+    // Rains under the text are drawn again on the next frame.
+    rmatrix.invalidate_screen(row, 0..text.chars().count() as u16);
+    Ok(())
+}
+
 fn try_set_config_param(rmatrix: &mut Rmatrix, param: ConfigParam, need_report: &mut Option<fs::File>) -> Result<(), String> {
     let (name, value) = param.split();
     match name.to_lowercase().as_str() {
@@ -171,7 +347,17 @@ fn try_set_config_param(rmatrix: &mut Rmatrix, param: ConfigParam, need_report:
                 ConfigVal::Range(box_v1, box_v2) => {
                     if let (ConfigVal::Num(v1), ConfigVal::Num(v2)) = (*box_v1, *box_v2) {
                         if v1 < v2 {
-                            rmatrix.speed = v1 as u16..v2 as u16;
+                            // Speed used to be in cells per frame, such ranges now barely move.
+                            if v2 <= OLD_SPEED_MAX {
+                                if let Some(report) = need_report.as_mut() {
+                                    let _ = write!(
+                                        report,
+                                        "Speed `{}..{}` looks like cells per frame, speed is in cells per second (`60..180`).",
+                                        v1, v2
+                                    );
+                                }
+                            }
+                            rmatrix.speed = v1..v2;
                             return Ok(());
                         }
                     }
@@ -180,7 +366,7 @@ fn try_set_config_param(rmatrix: &mut Rmatrix, param: ConfigParam, need_report:
                 _ => {}
             }
 
-            Err("Speed is range of number (`1..3`).".to_string())
+            Err("Speed is range of number (`60..180`).".to_string())
         }
         "length" => {
             match value {
@@ -234,6 +420,132 @@ fn try_set_config_param(rmatrix: &mut Rmatrix, param: ConfigParam, need_report:
                 "Color is range of tuple (`(0, 0, 0)..(0, 255, 0)`), tuple of number (`(0, 255, 0)`) or `nil`.".to_string()
             )
         }
+        "gradient" => {
+            let color = config_val_to_color;
+            match value {
+                ConfigVal::Tuple(box_v) if box_v.len() >= 2 => {
+                    let last = (box_v.len() - 1) as f32;
+                    let stops = box_v
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, stop)| match stop {
+                            // A stop is a color or `(position, color)`.
+                            ConfigVal::Tuple(stop) if stop.len() == 2 => match &stop[..] {
+                                [ConfigVal::Num(pos), stop_color] => {
+                                    color(stop_color).map(|color| (*pos, color))
+                                }
+                                _ => None,
+                            },
+                            stop => color(stop).map(|color| (idx as f32 / last, color)),
+                        })
+                        .collect::<Option<Vec<_>>>();
+                    if let Some(mut stops) = stops {
+                        stops.sort_by(|(pos1, _), (pos2, _)| pos1.total_cmp(pos2));
+                        rmatrix.gradient = Some(Gradient(stops));
+                        return Ok(());
+                    }
+                }
+                ConfigVal::Nil => {
+                    rmatrix.gradient = None;
+                    return Ok(());
+                }
+                _ => {}
+            }
+
+            Err(
+                "Gradient is tuple of colors or `(position, color)` stops (`((0, 0, 0), (0.7, (0, 255, 0)), (180, 255, 180))`) or `nil`.".to_string()
+            )
+        }
+        "color_space" => {
+            if let ConfigVal::String(name) = value {
+                if let Some(space) = ColorSpace::from_name(&name) {
+                    rmatrix.color_space = space;
+                    return Ok(());
+                }
+            }
+
+            Err(
+                "Color space is `\"srgb\"`, `\"linear\"`, `\"hsv\"`, `\"hsl\"` or `\"oklab\"`."
+                    .to_string(),
+            )
+        }
+        "rain_color" => {
+            match value {
+                ConfigVal::Nil => {
+                    rmatrix.rain_color = None;
+                    return Ok(());
+                }
+                value => {
+                    if let Some(choice) = config_val_to_color_choice(&value) {
+                        rmatrix.rain_color = Some(choice);
+                        return Ok(());
+                    }
+                }
+            }
+
+            Err(
+                "Rain color is range of tuple (`(255, 0, 0)..(128, 0, 128)`), tuple of colors (`((0, 255, 0), (0, 255, 255))`) or `nil`.".to_string()
+            )
+        }
+        "rain_head_color" => {
+            match value {
+                ConfigVal::Nil => {
+                    rmatrix.rain_head_color = None;
+                    return Ok(());
+                }
+                value => {
+                    if let Some(choice) = config_val_to_color_choice(&value) {
+                        rmatrix.rain_head_color = Some(choice);
+                        return Ok(());
+                    }
+                }
+            }
+
+            Err(
+                "Rain head color is range of tuple (`(255, 255, 255)..(255, 200, 255)`), tuple of colors (`((255, 255, 255), (255, 200, 255))`) or `nil`.".to_string()
+            )
+        }
+        "hue_mode" => {
+            if let ConfigVal::String(name) = value {
+                if let Some(mode) = HueMode::from_name(&name) {
+                    rmatrix.hue_mode = mode;
+                    return Ok(());
+                }
+            }
+
+            Err("Hue mode is `\"none\"`, `\"column\"`, `\"rain\"` or `\"time\"`.".to_string())
+        }
+        "hue_speed" => {
+            if let ConfigVal::Num(v) = value {
+                rmatrix.hue_speed = v;
+                return Ok(());
+            }
+
+            Err("Hue speed is number (`30`).".to_string())
+        }
+        "hue_spread" => {
+            if let ConfigVal::Num(v) = value {
+                rmatrix.hue_spread = v;
+                return Ok(());
+            }
+
+            Err("Hue spread is number (`360`).".to_string())
+        }
+        "hue_saturation" => {
+            match value {
+                ConfigVal::Num(v) if (0.0..=1.0).contains(&v) => {
+                    rmatrix.hue_saturation = Some(v);
+                    return Ok(());
+                }
+                ConfigVal::Nil => {
+                    rmatrix.hue_saturation = None;
+                    return Ok(());
+                }
+                _ => {}
+            }
+
+            Err("Hue saturation is number from 0 to 1 (`0.8`) or `nil`.".to_string())
+        }
         "head_color" => {
             match value {
                 ConfigVal::Tuple(box_v) => {
@@ -267,6 +579,48 @@ fn try_set_config_param(rmatrix: &mut Rmatrix, param: ConfigParam, need_report:
 
             Err("Interpolate color koef is number (`1.25`) or `nil`.".to_string())
         }
+        "curve" => {
+            match value {
+                ConfigVal::String(name) => {
+                    if let Some(curve) = BrightnessCurve::from_name(&name) {
+                        rmatrix.curve = Some(curve);
+                        return Ok(());
+                    }
+                }
+                ConfigVal::Tuple(box_v) => {
+                    let points = box_v
+                        .iter()
+                        .map(|point| match point {
+                            ConfigVal::Num(v) => Some(*v),
+                            _ => None,
+                        })
+                        .collect::<Option<Vec<_>>>();
+                    if let Some(points) = points.filter(|points| !points.is_empty()) {
+                        rmatrix.curve = Some(BrightnessCurve::Points(points));
+                        return Ok(());
+                    }
+                }
+                ConfigVal::Nil => {
+                    rmatrix.curve = None;
+                    return Ok(());
+                }
+                _ => {}
+            }
+
+            Err(concat!(
+                "Curve is `\"linear\"`, `\"exponential\"`, `\"ease_in\"`, `\"ease_out\"`, ",
+                "`\"ease_in_out\"`, `\"sigmoid\"`, tuple of number (`(0, 0.2, 1)`) or `nil`."
+            )
+            .to_string())
+        }
+        "head_cells" => {
+            if let ConfigVal::Num(v) = value {
+                rmatrix.head_cells = v as u16;
+                return Ok(());
+            }
+
+            Err("Head cells is number (`1`).".to_string())
+        }
         "min_brightnes" => {
             match value {
                 ConfigVal::Num(v) => {
@@ -290,6 +644,141 @@ fn try_set_config_param(rmatrix: &mut Rmatrix, param: ConfigParam, need_report:
 
             Err("Density is number (`0.7`).".to_string())
         }
+        "direction" => {
+            match value {
+                ConfigVal::String(name) => {
+                    if let Some(&(_, angle)) = DIRECTIONS
+                        .iter()
+                        .find(|(direction, _)| direction.eq_ignore_ascii_case(&name))
+                    {
+                        rmatrix.direction = angle;
+                        return Ok(());
+                    }
+                }
+                ConfigVal::Num(v) => {
+                    rmatrix.direction = v.rem_euclid(360.0);
+                    return Ok(());
+                }
+                _ => {}
+            }
+
+            Err(
+                "Direction is `\"down\"`, `\"up\"`, `\"left\"`, `\"right\"` or angle in degrees (`45`).".to_string()
+            )
+        }
+        "wind" => {
+            match value {
+                ConfigVal::Tuple(box_v) => {
+                    if let [ConfigVal::Num(x), ConfigVal::Num(y)] = box_v[..] {
+                        rmatrix.wind = (x, y);
+                        return Ok(());
+                    }
+                }
+                ConfigVal::Nil => {
+                    rmatrix.wind = (0.0, 0.0);
+                    return Ok(());
+                }
+                _ => {}
+            }
+
+            Err("Wind is tuple of number (`(-10, 0)`) or `nil`.".to_string())
+        }
+        "gravity" => {
+            if let ConfigVal::Num(v) = value {
+                rmatrix.gravity = v;
+                return Ok(());
+            }
+
+            Err("Gravity is number (`20`).".to_string())
+        }
+        "attractors" => {
+            fn attractor(value: &ConfigVal) -> Option<(f32, f32, f32)> {
+                match value {
+                    ConfigVal::Tuple(box_v) => match box_v[..] {
+                        [ConfigVal::Num(x), ConfigVal::Num(y), ConfigVal::Num(strength)] => {
+                            Some((x, y, strength))
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+
+            match &value {
+                ConfigVal::Tuple(box_v) => {
+                    // A single attractor may skip the outer tuple.
+                    let attractors = match attractor(&value) {
+                        Some(attractor) => Some(vec![attractor]),
+                        None => box_v.iter().map(attractor).collect(),
+                    };
+                    if let Some(attractors) = attractors {
+                        rmatrix.attractors = attractors;
+                        return Ok(());
+                    }
+                }
+                ConfigVal::Nil => {
+                    rmatrix.attractors.clear();
+                    return Ok(());
+                }
+                _ => {}
+            }
+
+            Err(
+                "Attractors is tuple of `(x, y, strength)` tuples (`((0.5, 0.5, 300), (0.2, 0.8, -100))`) or `nil`.".to_string()
+            )
+        }
+        "spawn" => {
+            if let ConfigVal::String(name) = value {
+                if let Some(spawn) = SpawnStrategy::from_name(&name) {
+                    rmatrix.spawn = spawn;
+                    return Ok(());
+                }
+            }
+
+            Err("Spawn is `\"uniform\"`, `\"balanced\"` or `\"noise\"`.".to_string())
+        }
+        "min_gap" => {
+            if let ConfigVal::Num(v) = value {
+                rmatrix.min_gap = v as u16;
+                return Ok(());
+            }
+
+            Err("Min gap is number (`1`).".to_string())
+        }
+        "density_pattern" => {
+            if let ConfigVal::String(name) = value {
+                if let Some(pattern) = DensityPattern::from_name(&name) {
+                    rmatrix.density_pattern = pattern;
+                    return Ok(());
+                }
+            }
+
+            Err("Density pattern is `\"flat\"`, `\"noise\"` or `\"wave\"`.".to_string())
+        }
+        "pattern_scale" => {
+            if let ConfigVal::Num(v) = value {
+                rmatrix.pattern_scale = v;
+                return Ok(());
+            }
+
+            Err("Pattern scale is number (`0.05`).".to_string())
+        }
+        "pattern_speed" => {
+            if let ConfigVal::Num(v) = value {
+                rmatrix.pattern_speed = v;
+                return Ok(());
+            }
+
+            Err("Pattern speed is number (`0.1`).".to_string())
+        }
+        "pattern_amplitude" => {
+            if let ConfigVal::Num(v) = value {
+                rmatrix.pattern_amplitude = v;
+                return Ok(());
+            }
+
+            Err("Pattern amplitude is number (`0.8`).".to_string())
+        }
         "is_bold" => {
             if let ConfigVal::Bool(b) = value {
                 rmatrix.is_bold = b;
@@ -306,6 +795,62 @@ fn try_set_config_param(rmatrix: &mut Rmatrix, param: ConfigParam, need_report:
 
             Err("Default rain is bool (`true`).".to_string())
         }
+        "is_glyph_grid" => {
+            if let ConfigVal::Bool(b) = value {
+                rmatrix.is_glyph_grid = b;
+                return Ok(());
+            }
+
+            Err("Glyph grid is bool (`false`).".to_string())
+        }
+        "mutation_rate" => {
+            if let ConfigVal::Num(v) = value {
+                rmatrix.mutation_rate = v;
+                return Ok(());
+            }
+
+            Err("Mutation rate is number (`0.1`).".to_string())
+        }
+        "head_mutation_rate" => {
+            match value {
+                ConfigVal::Num(v) => {
+                    rmatrix.head_mutation_rate = Some(v);
+                    return Ok(());
+                }
+                ConfigVal::Nil => {
+                    rmatrix.head_mutation_rate = None;
+                    return Ok(());
+                }
+                _ => {}
+            }
+
+            Err("Head mutation rate is number (`2`) or `nil`.".to_string())
+        }
+        "blend" => {
+            if let ConfigVal::String(name) = value {
+                if let Some(blend) = BlendMode::from_name(&name) {
+                    rmatrix.blend = blend;
+                    return Ok(());
+                }
+            }
+
+            Err("Blend is `\"max\"`, `\"additive\"` or `\"head_on_top\"`.".to_string())
+        }
+        "decay_time" => {
+            match value {
+                ConfigVal::Num(v) if v > 0.0 => {
+                    rmatrix.decay_time = Some(v);
+                    return Ok(());
+                }
+                ConfigVal::Nil => {
+                    rmatrix.decay_time = None;
+                    return Ok(());
+                }
+                _ => {}
+            }
+
+            Err("Decay time is positive number (`1.5`) or `nil`.".to_string())
+        }
         "delay" => {
             if let ConfigVal::Num(v) = value {
                 rmatrix.delay = time::Duration::from_millis(v as u64);
@@ -314,6 +859,62 @@ fn try_set_config_param(rmatrix: &mut Rmatrix, param: ConfigParam, need_report:
 
             Err("Delay is number (`16`).".to_string())
         }
+        "tick_rate" => {
+            if let ConfigVal::Num(v) = value {
+                if v > 0.0 {
+                    rmatrix.tick_rate = v;
+                    return Ok(());
+                }
+            }
+
+            Err("Tick rate is positive number (`120`).".to_string())
+        }
+        "is_adaptive" => {
+            if let ConfigVal::Bool(b) = value {
+                rmatrix.is_adaptive = b;
+                return Ok(());
+            }
+
+            Err("Adaptive is bool (`true`).".to_string())
+        }
+        "show_stats" => {
+            if let ConfigVal::Bool(b) = value {
+                rmatrix.show_stats = b;
+                return Ok(());
+            }
+
+            Err("Show stats is bool (`false`).".to_string())
+        }
+        "max_bytes_per_frame" => {
+            match value {
+                ConfigVal::Num(v) if v >= 1.0 => {
+                    rmatrix.max_bytes_per_frame = Some(v as usize);
+                    return Ok(());
+                }
+                ConfigVal::Nil => {
+                    rmatrix.max_bytes_per_frame = None;
+                    return Ok(());
+                }
+                _ => {}
+            }
+
+            Err("Max bytes per frame is positive number (`4096`) or `nil`.".to_string())
+        }
+        "synchronized_output" => {
+            match value {
+                ConfigVal::Bool(b) => {
+                    rmatrix.synchronized_output = Some(b);
+                    return Ok(());
+                }
+                ConfigVal::Nil => {
+                    rmatrix.synchronized_output = None;
+                    return Ok(());
+                }
+                _ => {}
+            }
+
+            Err("Synchronized output is bool (`true`) or `nil`.".to_string())
+        }
         "utf8" => {
             if let ConfigVal::Bool(b) = value {
                 if b {
@@ -328,8 +929,14 @@ fn try_set_config_param(rmatrix: &mut Rmatrix, param: ConfigParam, need_report:
         }
         "error_report_file" => {
             match value {
-                ConfigVal::String(writer_name) => *need_report = fs::File::create(writer_name).ok(),
-                ConfigVal::Nil => *need_report = None,
+                ConfigVal::String(writer_name) => {
+                    *need_report = fs::File::create(&writer_name).ok();
+                    rmatrix.error_report_file = Some(writer_name);
+                }
+                ConfigVal::Nil => {
+                    *need_report = None;
+                    rmatrix.error_report_file = None;
+                }
                 _ => return Err("error_report_file is String (`config_error.txt`) or `nil`".to_string()),
             }
             Ok(())
@@ -338,53 +945,605 @@ fn try_set_config_param(rmatrix: &mut Rmatrix, param: ConfigParam, need_report:
     }
 }
 
-fn rmatrix_from_config(config: &str, rmatrix: &mut Rmatrix) {
+fn read_config_params(
+    config: &Path,
+    include_stack: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+    params: &mut Vec<Result<ConfigParam, String>>,
+) {
+    let slurp_config = match fs::read_to_string(config) {
+        Ok(slurp_config) => slurp_config,
+        Err(err) => {
+            params.push(Err(format!(
+                "Can't read `{}`: {}.\n",
+                config.display(),
+                err
+            )));
+            return;
+        }
+    };
+
+    let canonical_config = fs::canonicalize(config).unwrap_or_else(|_| config.to_path_buf());
+    if !files.contains(&canonical_config) {
+        files.push(canonical_config.clone());
+    }
+    include_stack.push(canonical_config);
+
+    // Errors of included files are prefixed with the file they come from.
+    let err_prefix = if include_stack.len() > 1 {
+        format!("{}: ", config.display())
+    } else {
+        String::new()
+    };
+
+    let config_params = match config.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml_params(&slurp_config),
+        Some("json") => json_params(&slurp_config),
+        _ => {
+            let mut config_params = Vec::new();
+            let mut parser = ConfigParser::new(&slurp_config);
+            while let Some(res) = parser.parse() {
+                config_params.push(res.map_err(|err| format!("{}", err)));
+            }
+            config_params
+        }
+    };
+
+    for res in config_params {
+        match res {
+            Ok(param) if param.name() == "include" => {
+                let include = if let ConfigVal::String(include) = param.val() {
+                    config
+                        .parent()
+                        .unwrap_or_else(|| Path::new(""))
+                        .join(include)
+                } else {
+                    params.push(Err(format!(
+                        "{}include is String (`\"base.rm\"`).",
+                        err_prefix
+                    )));
+                    continue;
+                };
+
+                include_config_params(&include, &param, &err_prefix, include_stack, files, params);
+            }
+            Ok(param) if param.name() == "theme" => {
+                let name = match param.val() {
+                    ConfigVal::String(name) if themes::is_theme_name(name) => name,
+                    _ => {
+                        params.push(Err(format!(
+                            "{}theme is String of letters, digits, `_` and `-` (`\"amber\"`).",
+                            err_prefix
+                        )));
+                        continue;
+                    }
+                };
+
+                if let Some(path) = themes::theme_path(name).filter(|path| path.is_file()) {
+                    include_config_params(&path, &param, &err_prefix, include_stack, files, params);
+                } else if let Some(theme) = themes::builtin_theme(name) {
+                    let start = params.len();
+                    let mut parser = ConfigParser::new(theme);
+                    while let Some(res) = parser.parse() {
+                        params.push(res.map_err(|err| format!("{}{}: {}", err_prefix, name, err)));
+                    }
+                    inherit_profile(&mut params[start..], &param);
+                } else {
+                    params.push(Err(format!("{}Unknown theme `{}`.", err_prefix, name)));
+                }
+            }
+            Ok(param) => params.push(Ok(param)),
+            Err(err) => params.push(Err(format!("{}{}", err_prefix, err))),
+        }
+    }
+
+    let _ = include_stack.pop();
+}
+
+// Read `include` in place of `param`, unless it is already being read.
+fn include_config_params(
+    include: &Path,
+    param: &ConfigParam,
+    err_prefix: &str,
+    include_stack: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+    params: &mut Vec<Result<ConfigParam, String>>,
+) {
+    let canonical_include = fs::canonicalize(include).unwrap_or_else(|_| include.to_path_buf());
+    if include_stack.contains(&canonical_include) {
+        params.push(Err(format!(
+            "{}include cycle `{}`.",
+            err_prefix,
+            include.display()
+        )));
+        return;
+    }
+
+    let start = params.len();
+    read_config_params(include, include_stack, files, params);
+    inherit_profile(&mut params[start..], param);
+}
+
+// Parameters of a file included inside of a profile section belong to that
+// profile.
+fn inherit_profile(params: &mut [Result<ConfigParam, String>], param: &ConfigParam) {
+    for include_param in params.iter_mut().flatten() {
+        if include_param.profile().is_none() {
+            include_param.set_profile(param.profile().map(str::to_string));
+        }
+    }
+}
+
+/// Apply `config` (and the files it includes) to `rmatrix` and return every
+/// file that was read. `profile` takes precedence over the `profile` key.
+fn rmatrix_from_config(config: &str, profile: Option<&str>, rmatrix: &mut Rmatrix) -> Vec<PathBuf> {
     fn write_ignore<W: Write>(report_writer: &mut Option<W>, err: String) {
         if let Some(write) = report_writer.as_mut() {
             write.write_all(err.as_bytes()).unwrap()
         }
     }
 
+    let mut files = Vec::new();
+    let mut params = Vec::new();
+    read_config_params(Path::new(config), &mut Vec::new(), &mut files, &mut params);
+
+    let profile = profile.map(str::to_string).or_else(|| {
+        params.iter().flatten().rev().find_map(|param| {
+            match (param.name(), param.profile(), param.val()) {
+                ("profile", None, ConfigVal::String(profile)) => Some(profile.clone()),
+                _ => None,
+            }
+        })
+    });
+    let is_known_profile = profile.is_none()
+        || params
+            .iter()
+            .flatten()
+            .any(|param| param.profile() == profile.as_deref());
+
     let mut report_writer: Option<fs::File> = None;
-    let config = fs::read_to_string(config);
-    if let Ok(ref config) = config {
-        let mut parser = ConfigParser::new(config);
-        while let Some(res) = parser.parse() {
-            match res {
-                Ok(param) => {
-                    let res = try_set_config_param(rmatrix, param, &mut report_writer);
-                    if let Err(err) = res {
-                        write_ignore(&mut report_writer, err);
-                    }
+    for res in params {
+        match res {
+            Ok(param) if param.name() == "profile" => {
+                if let ConfigVal::String(_) | ConfigVal::Nil = param.val() {
+                    continue;
+                }
+                write_ignore(
+                    &mut report_writer,
+                    "profile is String (`\"night\"`) or `nil`.".to_string(),
+                );
+            }
+            Ok(param) => {
+                if param.profile().is_some() && param.profile() != profile.as_deref() {
+                    continue;
+                }
+
+                let res = try_set_config_param(rmatrix, param, &mut report_writer);
+                if let Err(err) = res {
+                    write_ignore(&mut report_writer, err);
                 }
-                Err(err) => write_ignore(&mut report_writer, format!("{}", err)),
             }
+            Err(err) => write_ignore(&mut report_writer, err),
         }
     }
+
+    if !is_known_profile {
+        write_ignore(
+            &mut report_writer,
+            format!("Unknown profile `{}`.", profile.unwrap_or_default()),
+        );
+    }
+
+    files
+}
+
+fn color_to_config_val(color: &RColor) -> ConfigVal {
+    let (r, g, b) = color.tuple();
+    ConfigVal::Tuple(vec![
+        ConfigVal::Num(r as f32),
+        ConfigVal::Num(g as f32),
+        ConfigVal::Num(b as f32),
+    ])
+}
+
+fn config_val_to_color(value: &ConfigVal) -> Option<RColor> {
+    match value {
+        ConfigVal::Tuple(box_v) => match box_v[..] {
+            [ConfigVal::Num(c1), ConfigVal::Num(c2), ConfigVal::Num(c3)] => {
+                Some(RColor::new(c1 as u8, c2 as u8, c3 as u8))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// A range of colors, a tuple of colors or a single color.
+fn config_val_to_color_choice(value: &ConfigVal) -> Option<ColorChoice> {
+    match value {
+        ConfigVal::Range(start, end) => Some(ColorChoice::Range(
+            config_val_to_color(start)?,
+            config_val_to_color(end)?,
+        )),
+        ConfigVal::Tuple(box_v) if !box_v.is_empty() => config_val_to_color(value)
+            .map(|color| vec![color])
+            .or_else(|| box_v.iter().map(config_val_to_color).collect())
+            .map(ColorChoice::Palette),
+        _ => None,
+    }
+}
+
+fn color_choice_to_config_val(choice: &Option<ColorChoice>) -> ConfigVal {
+    match choice {
+        Some(ColorChoice::Range(start, end)) => ConfigVal::Range(
+            Box::new(color_to_config_val(start)),
+            Box::new(color_to_config_val(end)),
+        ),
+        Some(ColorChoice::Palette(colors)) if colors.len() == 1 => color_to_config_val(&colors[0]),
+        Some(ColorChoice::Palette(colors)) => {
+            ConfigVal::Tuple(colors.iter().map(color_to_config_val).collect())
+        }
+        None => ConfigVal::Nil,
+    }
+}
+
+fn rmatrix_to_config_params(rmatrix: &Rmatrix) -> Vec<ConfigParam> {
+    fn range_val<T: Copy + Into<f32>>(range: &core::ops::Range<T>) -> ConfigVal {
+        ConfigVal::Range(
+            Box::new(ConfigVal::Num(range.start.into())),
+            Box::new(ConfigVal::Num(range.end.into())),
+        )
+    }
+    fn maybe_num_val(num: Option<f32>) -> ConfigVal {
+        num.map(ConfigVal::Num).unwrap_or(ConfigVal::Nil)
+    }
+
+    let color = if let Some(start_color) = rmatrix.start_gradient_color.as_ref() {
+        ConfigVal::Range(
+            Box::new(color_to_config_val(start_color)),
+            Box::new(color_to_config_val(&rmatrix.color)),
+        )
+    } else {
+        color_to_config_val(&rmatrix.color)
+    };
+    let gradient = match &rmatrix.gradient {
+        Some(Gradient(stops)) => {
+            let last = (stops.len() - 1) as f32;
+            let is_even = stops
+                .iter()
+                .enumerate()
+                .all(|(idx, (pos, _))| (pos - idx as f32 / last).abs() < 1e-4);
+            ConfigVal::Tuple(
+                stops
+                    .iter()
+                    .map(|(pos, color)| {
+                        if is_even {
+                            color_to_config_val(color)
+                        } else {
+                            ConfigVal::Tuple(vec![ConfigVal::Num(*pos), color_to_config_val(color)])
+                        }
+                    })
+                    .collect(),
+            )
+        }
+        None => ConfigVal::Nil,
+    };
+    let head_color = rmatrix
+        .head_color
+        .as_ref()
+        .map(color_to_config_val)
+        .unwrap_or(ConfigVal::Nil);
+
+    let direction = DIRECTIONS
+        .iter()
+        .find(|&&(_, angle)| angle == rmatrix.direction)
+        .map(|(name, _)| ConfigVal::String(name.to_string()))
+        .unwrap_or(ConfigVal::Num(rmatrix.direction));
+
+    let curve = match &rmatrix.curve {
+        Some(BrightnessCurve::Points(points)) => {
+            ConfigVal::Tuple(points.iter().copied().map(ConfigVal::Num).collect())
+        }
+        Some(curve) => ConfigVal::String(curve.name().unwrap_or_default().to_string()),
+        None => ConfigVal::Nil,
+    };
+    let attractors = if rmatrix.attractors.is_empty() {
+        ConfigVal::Nil
+    } else {
+        ConfigVal::Tuple(
+            rmatrix
+                .attractors
+                .iter()
+                .map(|&(x, y, strength)| {
+                    ConfigVal::Tuple(vec![
+                        ConfigVal::Num(x),
+                        ConfigVal::Num(y),
+                        ConfigVal::Num(strength),
+                    ])
+                })
+                .collect(),
+        )
+    };
+
+    vec![
+        ConfigParam::new(
+            "error_report_file",
+            rmatrix
+                .error_report_file
+                .clone()
+                .map(ConfigVal::String)
+                .unwrap_or(ConfigVal::Nil),
+        ),
+        ConfigParam::new("speed", range_val(&rmatrix.speed)),
+        ConfigParam::new("length", range_val(&rmatrix.len)),
+        ConfigParam::new("color", color),
+        ConfigParam::new("gradient", gradient),
+        ConfigParam::new(
+            "color_space",
+            ConfigVal::String(rmatrix.color_space.name().to_string()),
+        ),
+        ConfigParam::new(
+            "hue_mode",
+            ConfigVal::String(rmatrix.hue_mode.name().to_string()),
+        ),
+        ConfigParam::new("hue_speed", ConfigVal::Num(rmatrix.hue_speed)),
+        ConfigParam::new("hue_spread", ConfigVal::Num(rmatrix.hue_spread)),
+        ConfigParam::new("hue_saturation", maybe_num_val(rmatrix.hue_saturation)),
+        ConfigParam::new("head_color", head_color),
+        ConfigParam::new(
+            "rain_color",
+            color_choice_to_config_val(&rmatrix.rain_color),
+        ),
+        ConfigParam::new(
+            "rain_head_color",
+            color_choice_to_config_val(&rmatrix.rain_head_color),
+        ),
+        ConfigParam::new(
+            "interpolate_color_koef",
+            maybe_num_val(rmatrix.interpolate_color_koef),
+        ),
+        ConfigParam::new("curve", curve),
+        ConfigParam::new("head_cells", ConfigVal::Num(rmatrix.head_cells as f32)),
+        ConfigParam::new("min_brightnes", maybe_num_val(rmatrix.min_brightnes)),
+        ConfigParam::new("density", ConfigVal::Num(rmatrix.density)),
+        ConfigParam::new("direction", direction),
+        ConfigParam::new(
+            "wind",
+            ConfigVal::Tuple(vec![
+                ConfigVal::Num(rmatrix.wind.0),
+                ConfigVal::Num(rmatrix.wind.1),
+            ]),
+        ),
+        ConfigParam::new("gravity", ConfigVal::Num(rmatrix.gravity)),
+        ConfigParam::new("attractors", attractors),
+        ConfigParam::new("spawn", ConfigVal::String(rmatrix.spawn.name().to_string())),
+        ConfigParam::new("min_gap", ConfigVal::Num(rmatrix.min_gap as f32)),
+        ConfigParam::new(
+            "density_pattern",
+            ConfigVal::String(rmatrix.density_pattern.name().to_string()),
+        ),
+        ConfigParam::new("pattern_scale", ConfigVal::Num(rmatrix.pattern_scale)),
+        ConfigParam::new("pattern_speed", ConfigVal::Num(rmatrix.pattern_speed)),
+        ConfigParam::new(
+            "pattern_amplitude",
+            ConfigVal::Num(rmatrix.pattern_amplitude),
+        ),
+        ConfigParam::new("is_bold", ConfigVal::Bool(rmatrix.is_bold)),
+        ConfigParam::new("is_default_rain", ConfigVal::Bool(rmatrix.is_default_rain)),
+        ConfigParam::new("is_glyph_grid", ConfigVal::Bool(rmatrix.is_glyph_grid)),
+        ConfigParam::new("mutation_rate", ConfigVal::Num(rmatrix.mutation_rate)),
+        ConfigParam::new(
+            "head_mutation_rate",
+            maybe_num_val(rmatrix.head_mutation_rate),
+        ),
+        ConfigParam::new("decay_time", maybe_num_val(rmatrix.decay_time)),
+        ConfigParam::new("blend", ConfigVal::String(rmatrix.blend.name().to_string())),
+        ConfigParam::new("delay", ConfigVal::Num(rmatrix.delay.as_millis() as f32)),
+        ConfigParam::new("tick_rate", ConfigVal::Num(rmatrix.tick_rate)),
+        ConfigParam::new("is_adaptive", ConfigVal::Bool(rmatrix.is_adaptive)),
+        ConfigParam::new("show_stats", ConfigVal::Bool(rmatrix.show_stats)),
+        ConfigParam::new(
+            "max_bytes_per_frame",
+            maybe_num_val(rmatrix.max_bytes_per_frame.map(|bytes| bytes as f32)),
+        ),
+        ConfigParam::new(
+            "synchronized_output",
+            rmatrix
+                .synchronized_output
+                .map(ConfigVal::Bool)
+                .unwrap_or(ConfigVal::Nil),
+        ),
+        ConfigParam::new("utf8", ConfigVal::Bool(rmatrix.is_utf8)),
+    ]
+}
+
+fn rmatrix_to_config(rmatrix: &Rmatrix) -> String {
+    let mut document = ConfigDocument::parse(STARTER_CONFIG);
+    for param in rmatrix_to_config_params(rmatrix) {
+        document.set(&param, None, "");
+    }
+    document.to_string()
+}
+
+fn save_config(config: &str, profile: Option<&str>, rmatrix: &Rmatrix) -> io::Result<()> {
+    if Path::new(config).extension().and_then(|ext| ext.to_str()) != Some("rm") {
+        return Err(io::Error::other("only `.rm` configs can be saved"));
+    }
+
+    let slurp_config = fs::read_to_string(config).unwrap_or_default();
+    let mut document = ConfigDocument::parse(&slurp_config);
+
+    let profile = profile
+        .map(str::to_string)
+        .or_else(|| match document.get("profile", None) {
+            Some(ConfigVal::String(profile)) => Some(profile.clone()),
+            _ => None,
+        });
+    // Only settings that differ from what the file (with its includes and
+    // themes) gives are written, so everything else keeps coming from there.
+    let mut saved = Rmatrix::default();
+    let _ = rmatrix_from_config(config, profile.as_deref(), &mut saved);
+
+    let starter = ConfigDocument::parse(STARTER_CONFIG);
+    let params = rmatrix_to_config_params(rmatrix);
+    for (param, saved_param) in params.iter().zip(rmatrix_to_config_params(&saved)) {
+        if param.val() == saved_param.val() {
+            continue;
+        }
+        let doc = starter.doc(param.name()).unwrap_or_default();
+        document.set(param, profile.as_deref(), &doc);
+    }
+    fs::write(config, document.to_string())
+}
+
+fn format_config(config: &str) -> io::Result<()> {
+    // Other formats would be rewritten by `config.rm` rules.
+    if Path::new(config).extension().and_then(|ext| ext.to_str()) != Some("rm") {
+        return Err(io::Error::other("only `.rm` configs can be formatted"));
+    }
+
+    let slurp_config = fs::read_to_string(config)?;
+    let mut document = ConfigDocument::parse(&slurp_config);
+    document.format();
+    fs::write(config, document.to_string())
+}
+
+#[derive(Debug, Default)]
+struct Args {
+    print_config: bool,
+    init_config: Option<String>,
+    fmt: Option<String>,
+    profile: Option<String>,
+    config: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args::default();
+    let mut iter = std::env::args().skip(1).peekable();
+    if let Some("fmt") = iter.peek().map(String::as_str) {
+        let _ = iter.next();
+        args.fmt = Some(iter.next().unwrap_or_else(|| CONFIG_NAME.to_string()));
+    }
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--print-config" => args.print_config = true,
+            "--config" => match iter.next() {
+                Some(config) => args.config = Some(config),
+                None => return Err("`--config` expects a path.".to_string()),
+            },
+            "--profile" => match iter.next() {
+                Some(profile) => args.profile = Some(profile),
+                None => return Err("`--profile` expects a profile name.".to_string()),
+            },
+            "--init-config" => {
+                let path = match iter.peek() {
+                    Some(path) if !path.starts_with("--") => iter.next().unwrap(),
+                    _ => CONFIG_NAME.to_string(),
+                };
+                args.init_config = Some(path);
+            }
+            arg => {
+                return Err(format!(
+                    "Unexpected argument `{}`.\n\n{}",
+                    arg,
+                    concat!(
+                        "USAGE:\n",
+                        "    rsmatrix [--config PATH] [--profile NAME] [--print-config] [--init-config [PATH]]\n",
+                        "    rsmatrix fmt [PATH]\n",
+                        "\n",
+                        "    --config PATH           use `config.rm`, `.toml` or `.json` at PATH\n",
+                        "    --profile NAME          select the `[profile NAME]` config section\n",
+                        "    --print-config          print the effective configuration and exit\n",
+                        "    --init-config [PATH]    write a starter config (default `config.rm`) and exit\n",
+                        "    fmt [PATH]              format a `.rm` config (default `config.rm`) in place",
+                    )
+                ));
+            }
+        }
+    }
+    Ok(args)
 }
 
 const CONFIG_NAME: &str = "config.rm";
+// Layout and documentation of every parameter, the source of `--init-config`,
+// `--print-config` and the comments of parameters added on save.
+const STARTER_CONFIG: &str = include_str!("../config.rm");
+const MAX_CATCH_UP_TICKS: u32 = 8;
+const SYNC_QUERY_TIMEOUT: time::Duration = time::Duration::from_millis(200);
+// Top speed below which a `speed` range was likely written in cells per frame.
+const OLD_SPEED_MAX: f32 = 5.0;
+const STATUS_DURATION: time::Duration = time::Duration::from_secs(3);
+// Looked up in order when `--config` is not given.
+const CONFIG_NAMES: [&str; 3] = [CONFIG_NAME, "config.toml", "config.json"];
 
 fn main() -> crossterm::Result<()> {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    if let Some(path) = args.fmt {
+        if let Err(err) = format_config(&path) {
+            eprintln!("Can't format `{}`: {}.", path, err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = args.init_config {
+        if fs::metadata(&path).is_ok() {
+            eprintln!("`{}` already exists.", path);
+            std::process::exit(1);
+        }
+        fs::write(&path, rmatrix_to_config(&Rmatrix::default()))?;
+        return Ok(());
+    }
+
+    let config = args.config.clone().unwrap_or_else(|| {
+        CONFIG_NAMES
+            .iter()
+            .find(|config| fs::metadata(config).is_ok())
+            .unwrap_or(&CONFIG_NAME)
+            .to_string()
+    });
+
     let mut rmatrix = Rmatrix::default();
-    rmatrix_from_config(CONFIG_NAME, &mut rmatrix);
+    let config_files = rmatrix_from_config(&config, args.profile.as_deref(), &mut rmatrix);
+
+    if args.print_config {
+        print!("{}", rmatrix_to_config(&rmatrix));
+        return Ok(());
+    }
 
     let rmatrix = Arc::new(Mutex::new(rmatrix));
     let cloned_rmatrix = Arc::clone(&rmatrix);
+    let cloned_profile = args.profile.clone();
+    let cloned_config = config.clone();
 
     let mut watcher: RecommendedWatcher =
         Watcher::new_immediate(move |result: Result<Event, Error>| {
             let event = result.unwrap();
             if event.kind == EventKind::Modify(ModifyKind::Any) {
                 let mut new_rmatrix = cloned_rmatrix.lock().unwrap();
-                rmatrix_from_config(CONFIG_NAME, &mut new_rmatrix);
+                let _ = rmatrix_from_config(
+                    &cloned_config,
+                    cloned_profile.as_deref(),
+                    &mut new_rmatrix,
+                );
             }
         })
         .unwrap();
 
-    watcher
-        .watch(CONFIG_NAME, RecursiveMode::NonRecursive)
-        .unwrap();
+    watcher.watch(&config, RecursiveMode::NonRecursive).unwrap();
+    for config_file in config_files.iter().skip(1) {
+        watcher
+            .watch(config_file, RecursiveMode::NonRecursive)
+            .unwrap();
+    }
 
     let mut stdout = io::stdout();
     terminal::enable_raw_mode()?;
@@ -392,39 +1551,123 @@ fn main() -> crossterm::Result<()> {
         .execute(terminal::EnterAlternateScreen)?
         .execute(cursor::Hide)?
         .execute(cursor::SavePosition)?;
+    // Asked even when disabled, so enabling it on reload can use the answer.
+    let is_sync_supported = sync_output::is_supported(SYNC_QUERY_TIMEOUT);
 
     let (width, height) = terminal::size()?;
     rmatrix.lock().unwrap().resize(width, height);
 
+    let mut last_update = time::Instant::now();
+    let mut lag = time::Duration::from_secs(0);
+    let mut next_frame = last_update;
+    let mut pacer = FramePacer::default();
+    // Shown until the instant passes.
+    let mut status: Option<(String, time::Instant)> = None;
     loop {
-        if event::poll(rmatrix.lock().unwrap().delay())? {
+        let now = time::Instant::now();
+        lag += now - last_update;
+        last_update = now;
+
+        let (tick, delay) = {
+            let mut rmatrix = rmatrix.lock().unwrap();
+            let tick = rmatrix.tick();
+
+            let mut ticks = 0;
+            while lag >= tick {
+                // Drop the backlog instead of spiraling on a stalled terminal.
+                if ticks == MAX_CATCH_UP_TICKS {
+                    lag = time::Duration::from_secs(0);
+                    break;
+                }
+                rmatrix.update(tick);
+                lag -= tick;
+                ticks += 1;
+            }
+
+            (tick, rmatrix.delay())
+        };
+
+        if now >= next_frame {
+            let mut rmatrix = rmatrix.lock().unwrap();
+
+            let is_sync = rmatrix.synchronized_output.unwrap_or(is_sync_supported);
+            if is_sync {
+                stdout.queue(BeginSynchronizedUpdate)?;
+            }
+
+            let render_start = time::Instant::now();
+            let bytes = rmatrix.as_crossterm_render().render(&mut stdout)?;
+            pacer.frame(render_start, time::Instant::now(), bytes);
+
+            let delay = if rmatrix.is_adaptive {
+                pacer.adapt(delay)
+            } else {
+                pacer.reset_quality();
+                delay
+            };
+            rmatrix.quality = pacer.quality();
+
+            if rmatrix.show_stats {
+                render_stats(&mut stdout, &pacer, &mut rmatrix)?;
+            }
+            match &status {
+                Some((message, until)) if now < *until => {
+                    render_status(&mut stdout, message, &mut rmatrix)?;
+                }
+                _ => status = None,
+            }
+            if is_sync {
+                stdout.execute(EndSynchronizedUpdate)?;
+            }
+
+            // Skip the frames a slow terminal could not keep up with.
+            next_frame += delay;
+            let now = time::Instant::now();
+            if next_frame < now {
+                next_frame = now + delay;
+            }
+        }
+
+        let timeout = (tick - lag).min(next_frame.saturating_duration_since(time::Instant::now()));
+        if event::poll(timeout)? {
             match event::read()? {
-                event::Event::Key(event) => match event {
-                    event::KeyEvent {
-                        code: event::KeyCode::Esc,
-                        ..
-                    }
-                    | event::KeyEvent {
-                        code: event::KeyCode::Char('c'),
-                        modifiers: event::KeyModifiers::CONTROL,
-                    } => {
-                        break;
-                    }
-                    _ => {}
-                },
+                event::Event::Key(event::KeyEvent {
+                    code: event::KeyCode::Esc,
+                    ..
+                })
+                | event::Event::Key(event::KeyEvent {
+                    code: event::KeyCode::Char('c'),
+                    modifiers: event::KeyModifiers::CONTROL,
+                }) => {
+                    break;
+                }
+                event::Event::Key(event::KeyEvent {
+                    code: event::KeyCode::Char('s'),
+                    modifiers: event::KeyModifiers::NONE,
+                }) => {
+                    // A failed save must not tear down the animation.
+                    let message = match save_config(
+                        &config,
+                        args.profile.as_deref(),
+                        &rmatrix.lock().unwrap(),
+                    ) {
+                        Ok(()) => format!("Saved `{}`.", config),
+                        Err(err) => format!("Can't save `{}`: {}.", config, err),
+                    };
+                    status = Some((message, time::Instant::now() + STATUS_DURATION));
+                }
+                event::Event::Key(event::KeyEvent {
+                    code: event::KeyCode::Char('i'),
+                    modifiers: event::KeyModifiers::NONE,
+                }) => {
+                    let mut rmatrix = rmatrix.lock().unwrap();
+                    rmatrix.show_stats = !rmatrix.show_stats;
+                }
                 event::Event::Resize(width, height) => {
-                    stdout.queue(terminal::Clear(terminal::ClearType::All))?;
                     rmatrix.lock().unwrap().resize(width, height)
                 }
                 _ => {}
             }
-        } else {
-            rmatrix.lock().unwrap().update();
-            rmatrix
-                .lock()
-                .unwrap()
-                .as_crossterm_render()
-                .render(&mut stdout)?;
         }
     }
 
@@ -435,3 +1678,151 @@ fn main() -> crossterm::Result<()> {
     terminal::disable_raw_mode()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fresh directory for the files of one test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rsmatrix_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(dir: &Path, name: &str, config: &str) -> String {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, config).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn load(config: &str, profile: Option<&str>) -> Rmatrix {
+        let mut rmatrix = Rmatrix::default();
+        let _ = rmatrix_from_config(config, profile, &mut rmatrix);
+        rmatrix
+    }
+
+    #[test]
+    fn include_cycle_is_reported_and_rest_applies() {
+        let dir = temp_dir("cycle");
+        let config = write(&dir, "a.rm", "include :: \"b.rm\"\nspeed :: 30..90\n");
+        let _ = write(&dir, "b.rm", "include :: \"a.rm\"\ndensity :: 0.2\n");
+
+        let mut files = Vec::new();
+        let mut params = Vec::new();
+        read_config_params(Path::new(&config), &mut Vec::new(), &mut files, &mut params);
+        let errs = params
+            .iter()
+            .filter_map(|res| res.as_ref().err())
+            .collect::<Vec<_>>();
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].contains("include cycle"), "{}", errs[0]);
+        assert_eq!(files.len(), 2);
+
+        let rmatrix = load(&config, None);
+        assert_eq!(rmatrix.speed, 30.0..90.0);
+        assert_eq!(rmatrix.density, 0.2);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn include_is_relative_to_including_file() {
+        let dir = temp_dir("relative");
+        let config = write(&dir, "config.rm", "include :: \"sub/mid.rm\"\n");
+        let _ = write(
+            &dir,
+            "sub/mid.rm",
+            "include :: \"leaf.rm\"\nspeed :: 30..90\n",
+        );
+        let _ = write(&dir, "sub/leaf.rm", "density :: 0.2\n");
+        let _ = write(&dir, "leaf.rm", "density :: 0.9\n");
+
+        let mut rmatrix = Rmatrix::default();
+        let files = rmatrix_from_config(&config, None, &mut rmatrix);
+        assert_eq!(files.len(), 3);
+        assert_eq!(rmatrix.speed, 30.0..90.0);
+        assert_eq!(rmatrix.density, 0.2);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn include_and_theme_in_profile_belong_to_profile() {
+        let dir = temp_dir("profile_include");
+        let config = write(
+            &dir,
+            "config.rm",
+            "density :: 0.5\n[profile night]\ninclude :: \"night.rm\"\ntheme :: \"amber\"\n",
+        );
+        let _ = write(&dir, "night.rm", "density :: 0.2\n");
+
+        let rmatrix = load(&config, None);
+        assert_eq!(rmatrix.density, 0.5);
+        assert!(rmatrix.is_utf8);
+        let rmatrix = load(&config, Some("night"));
+        assert_eq!(rmatrix.density, 0.2);
+        assert!(!rmatrix.is_utf8);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn selected_profile_overrides_profile_key() {
+        let dir = temp_dir("profile_key");
+        let report = dir.join("report.txt");
+        let config = write(
+            &dir,
+            "config.rm",
+            &format!(
+                "error_report_file :: \"{}\"\nprofile :: \"day\"\n[profile day]\ndensity :: 0.1\n[profile night]\ndensity :: 0.2\n",
+                report.display()
+            ),
+        );
+
+        assert_eq!(load(&config, None).density, 0.1);
+        assert_eq!(load(&config, Some("night")).density, 0.2);
+        assert_eq!(fs::read_to_string(&report).unwrap(), "");
+
+        assert_eq!(load(&config, Some("dusk")).density, 0.7);
+        assert_eq!(
+            fs::read_to_string(&report).unwrap(),
+            "Unknown profile `dusk`."
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn save_writes_only_changed_settings() {
+        let dir = temp_dir("save");
+        let _ = write(&dir, "base.rm", "density :: 0.3\n");
+        let config = write(
+            &dir,
+            "config.rm",
+            "include :: \"base.rm\"\ntheme :: \"amber\"\nspeed :: 60..180\n\n[profile night]\ncolor :: (0, 0, 200)\n",
+        );
+
+        let mut rmatrix = load(&config, None);
+        rmatrix.speed = 30.0..90.0;
+        save_config(&config, None, &rmatrix).unwrap();
+        assert_eq!(
+            fs::read_to_string(&config).unwrap(),
+            "include :: \"base.rm\"\ntheme :: \"amber\"\nspeed :: 30..90\n\n[profile night]\ncolor :: (0, 0, 200)\n",
+        );
+
+        let mut rmatrix = load(&config, Some("night"));
+        rmatrix.density = 0.5;
+        save_config(&config, Some("night"), &rmatrix).unwrap();
+        assert_eq!(
+            fs::read_to_string(&config).unwrap(),
+            "include :: \"base.rm\"\ntheme :: \"amber\"\nspeed :: 30..90\n\n[profile night]\ncolor :: (0, 0, 200)\n\n# rain factor\ndensity :: 0.5\n",
+        );
+        assert_eq!(load(&config, None).density, 0.3);
+        assert_eq!(load(&config, Some("night")).density, 0.5);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,40 @@
+#![forbid(unsafe_code)]
+
+// Pseudo-random gradient direction of the lattice point `(x, y)`.
+#[inline]
+fn gradient(x: i32, y: i32) -> (f32, f32) {
+    let mut hash = (x as u32).wrapping_mul(0x27D4_EB2D) ^ (y as u32).wrapping_mul(0x1656_67B1);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x85EB_CA6B);
+    hash ^= hash >> 13;
+
+    let angle = (hash & 0xFFFF) as f32 / 0x10000 as f32 * core::f32::consts::TAU;
+    (angle.cos(), angle.sin())
+}
+
+#[inline]
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+#[inline]
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Smooth 2D gradient noise in about `-1.0..=1.0`, zero on integer points.
+pub fn perlin(x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (dx, dy) = (x - x0, y - y0);
+    let (ix, iy) = (x0 as i32, y0 as i32);
+
+    let dot = |cx: i32, cy: i32| {
+        let (gx, gy) = gradient(ix + cx, iy + cy);
+        gx * (dx - cx as f32) + gy * (dy - cy as f32)
+    };
+
+    let (u, v) = (fade(dx), fade(dy));
+    let top = lerp(dot(0, 0), dot(1, 0), u);
+    let bottom = lerp(dot(0, 1), dot(1, 1), u);
+    (lerp(top, bottom, v) * core::f32::consts::SQRT_2).clamp(-1.0, 1.0)
+}
@@ -0,0 +1,96 @@
+#![forbid(unsafe_code)]
+
+use std::time;
+
+// Weight of the newest sample in the moving averages.
+const SMOOTHING: f32 = 0.1;
+// Lower the quality once a render takes this share of the frame budget.
+const HIGH_LOAD: f32 = 0.9;
+// Raise it back while renders take less than this share.
+const LOW_LOAD: f32 = 0.5;
+const QUALITY_STEP: f32 = 0.05;
+const MIN_QUALITY: f32 = 0.2;
+
+/// Measures frame times and trades render rate and detail to hold the
+/// target frame rate.
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    render_time: f32,
+    frame_time: f32,
+    bytes: f32,
+    last_frame: Option<time::Instant>,
+    quality: f32,
+}
+
+impl Default for FramePacer {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            render_time: 0.0,
+            frame_time: 0.0,
+            bytes: 0.0,
+            last_frame: None,
+            quality: 1.0,
+        }
+    }
+}
+
+impl FramePacer {
+    #[inline]
+    fn smooth(avg: f32, sample: f32) -> f32 {
+        if avg == 0.0 {
+            sample
+        } else {
+            avg + (sample - avg) * SMOOTHING
+        }
+    }
+
+    /// Record a frame of `bytes` rendered between `start` and `end`.
+    pub fn frame(&mut self, start: time::Instant, end: time::Instant, bytes: usize) {
+        self.render_time = Self::smooth(self.render_time, (end - start).as_secs_f32());
+        self.bytes = Self::smooth(self.bytes, bytes as f32);
+        if let Some(last_frame) = self.last_frame {
+            self.frame_time = Self::smooth(self.frame_time, (start - last_frame).as_secs_f32());
+        }
+        self.last_frame = Some(start);
+    }
+
+    /// Adjust the quality toward the `delay` frame budget and return the
+    /// delay frames can actually be rendered at.
+    pub fn adapt(&mut self, delay: time::Duration) -> time::Duration {
+        let budget = delay.as_secs_f32();
+        if self.render_time > budget * HIGH_LOAD {
+            self.quality = (self.quality - QUALITY_STEP).max(MIN_QUALITY);
+        } else if self.render_time < budget * LOW_LOAD {
+            self.quality = (self.quality + QUALITY_STEP * 0.5).min(1.0);
+        }
+
+        delay.max(time::Duration::from_secs_f32(self.render_time))
+    }
+
+    #[inline]
+    pub fn reset_quality(&mut self) {
+        self.quality = 1.0;
+    }
+
+    #[inline]
+    pub fn quality(&self) -> f32 {
+        self.quality
+    }
+    #[inline]
+    pub fn fps(&self) -> f32 {
+        if self.frame_time > 0.0 {
+            1.0 / self.frame_time
+        } else {
+            0.0
+        }
+    }
+    #[inline]
+    pub fn bytes(&self) -> usize {
+        self.bytes as usize
+    }
+    #[inline]
+    pub fn render_time(&self) -> time::Duration {
+        time::Duration::from_secs_f32(self.render_time)
+    }
+}
@@ -2,22 +2,415 @@
 
 use core::ops::Range;
 use rand::prelude::*;
+use std::collections::VecDeque;
 use std::time;
 
+use crate::noise;
 use crate::RmatrixCrosstermRender;
 
+// Columns tried before a spawn is postponed to a later tick.
+const SPAWN_ATTEMPTS: usize = 8;
+// Columns per noise period and noise drift per second of `SpawnStrategy::Noise`.
+const SPAWN_NOISE_SCALE: f32 = 0.08;
+const SPAWN_NOISE_SPEED: f32 = 0.2;
+// Value named `name` in a `NAMES` table, ignoring case.
+fn value_of<T: Clone>(names: &[(&str, T)], name: &str) -> Option<T> {
+    names
+        .iter()
+        .find(|(value_name, _)| value_name.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.clone())
+}
+
+// Name of `value` in a `NAMES` table.
+fn name_of<T: PartialEq>(names: &[(&'static str, T)], value: &T) -> Option<&'static str> {
+    names
+        .iter()
+        .find(|(_, named)| named == value)
+        .map(|&(name, _)| name)
+}
+
+// Tick rates the simulation runs at, so a tick stays a representable duration.
+const TICK_RATES: core::ops::RangeInclusive<f32> = 0.1..=10_000.0;
+
 #[derive(Debug, Clone)]
 pub struct Rain {
-    pub(crate) x: u16,
-    pub(crate) y: u16,
+    // Head position in cells.
+    pub(crate) x: f32,
+    pub(crate) y: f32,
+    // Cells per second.
+    pub(crate) vx: f32,
+    pub(crate) vy: f32,
     pub(crate) length: u16,
-    pub(crate) speed: u16,
+    // Cells the head passed through, the head last, `length + 1` at most.
+    pub(crate) trail: VecDeque<(i32, i32)>,
+    // Glyph of each trail cell when the rain is not the default one.
+    pub(crate) glyphs: VecDeque<char>,
+    // Cells entered since the edge of the screen, picks the glyphs.
+    pub(crate) entered: i64,
 
     pub(crate) symbl_pos: u16,
     pub(crate) brightnes: Option<f32>,
+    // Share of `hue_spread` the colors of the rain are shifted by.
+    pub(crate) hue: f32,
+    // Picked at spawn from `rain_color` and `rain_head_color`.
+    pub(crate) color: Option<RColor>,
+    pub(crate) head_color: Option<RColor>,
+}
+
+/// Named directions in degrees, clockwise from the right.
+pub const DIRECTIONS: [(&str, f32); 4] = [
+    ("right", 0.0),
+    ("down", 90.0),
+    ("left", 180.0),
+    ("up", 270.0),
+];
+
+/// How the column of a new rain is picked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpawnStrategy {
+    /// Any column.
+    Uniform,
+    /// The columns with the fewest rains.
+    Balanced,
+    /// Columns favoured by slowly drifting noise, so rain gathers in bands.
+    Noise,
 }
 
-#[derive(Debug, Clone, Default)]
+impl SpawnStrategy {
+    pub const NAMES: [(&'static str, SpawnStrategy); 3] = [
+        ("uniform", SpawnStrategy::Uniform),
+        ("balanced", SpawnStrategy::Balanced),
+        ("noise", SpawnStrategy::Noise),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        value_of(&Self::NAMES, name)
+    }
+    pub fn name(&self) -> &'static str {
+        name_of(&Self::NAMES, self).unwrap()
+    }
+}
+
+/// How the density varies across the columns and over time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DensityPattern {
+    /// The same everywhere.
+    Flat,
+    /// Coherent noise, rain comes in drifting bands.
+    Noise,
+    /// Travelling sine wave, rain sweeps across the screen.
+    Wave,
+}
+
+impl DensityPattern {
+    pub const NAMES: [(&'static str, DensityPattern); 3] = [
+        ("flat", DensityPattern::Flat),
+        ("noise", DensityPattern::Noise),
+        ("wave", DensityPattern::Wave),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        value_of(&Self::NAMES, name)
+    }
+    pub fn name(&self) -> &'static str {
+        name_of(&Self::NAMES, self).unwrap()
+    }
+}
+
+/// How the brightness grows from the tail to the head of a rain.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BrightnessCurve {
+    Linear,
+    Exponential,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    Sigmoid,
+    /// Evenly spaced brightness from the tail to the head, linear between.
+    Points(Vec<f32>),
+}
+
+impl BrightnessCurve {
+    pub const NAMES: [(&'static str, BrightnessCurve); 6] = [
+        ("linear", BrightnessCurve::Linear),
+        ("exponential", BrightnessCurve::Exponential),
+        ("ease_in", BrightnessCurve::EaseIn),
+        ("ease_out", BrightnessCurve::EaseOut),
+        ("ease_in_out", BrightnessCurve::EaseInOut),
+        ("sigmoid", BrightnessCurve::Sigmoid),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        value_of(&Self::NAMES, name)
+    }
+    /// `None` for control points.
+    pub fn name(&self) -> Option<&'static str> {
+        name_of(&Self::NAMES, self)
+    }
+
+    /// Brightness at `t`, from `0.0` at the tail to `1.0` at the head.
+    pub fn at(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            BrightnessCurve::Linear => t,
+            BrightnessCurve::Exponential => (5.0 * t).exp_m1() / 5.0f32.exp_m1(),
+            BrightnessCurve::EaseIn => t * t,
+            BrightnessCurve::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            BrightnessCurve::EaseInOut => t * t * (3.0 - 2.0 * t),
+            BrightnessCurve::Sigmoid => {
+                let sigmoid = |t: f32| 1.0 / (1.0 + (-10.0 * (t - 0.5)).exp());
+                (sigmoid(t) - sigmoid(0.0)) / (sigmoid(1.0) - sigmoid(0.0))
+            }
+            BrightnessCurve::Points(points) => match points.len() {
+                0 => t,
+                1 => points[0],
+                len => {
+                    let pos = t * (len - 1) as f32;
+                    let idx = (pos as usize).min(len - 2);
+                    points[idx] + (points[idx + 1] - points[idx]) * (pos - idx as f32)
+                }
+            },
+        }
+    }
+}
+
+/// How overlapping rains are composited into one cell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    /// The brightest cell wins.
+    Max,
+    /// Colors add up, the brightest cell gives the glyph.
+    Additive,
+    /// Heads win over trails, otherwise the brightest cell wins.
+    HeadOnTop,
+}
+
+impl BlendMode {
+    pub const NAMES: [(&'static str, BlendMode); 3] = [
+        ("max", BlendMode::Max),
+        ("additive", BlendMode::Additive),
+        ("head_on_top", BlendMode::HeadOnTop),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        value_of(&Self::NAMES, name)
+    }
+    pub fn name(&self) -> &'static str {
+        name_of(&Self::NAMES, self).unwrap()
+    }
+
+    /// `cell` composited with `under`, where `top` is the cell that shows
+    /// among those under it. The same in any drawing order.
+    pub fn blend(&self, under: Cell, top: Cell, cell: Cell) -> Cell {
+        match self {
+            BlendMode::Max => {
+                let key = |cell: &Cell| (cell.brightnes(), cell.color, cell.symbl, cell.is_head);
+                if key(&cell) > key(&under) {
+                    cell
+                } else {
+                    under
+                }
+            }
+            // The glyph comes from one cell and not from a partial sum, which
+            // depends on the order.
+            BlendMode::Additive => Cell {
+                color: (
+                    cell.color.0.saturating_add(under.color.0),
+                    cell.color.1.saturating_add(under.color.1),
+                    cell.color.2.saturating_add(under.color.2),
+                ),
+                ..cell.top(top)
+            },
+            BlendMode::HeadOnTop => cell.top(under),
+        }
+    }
+}
+
+/// One terminal cell as drawn by the renderer.
+#[derive(Debug, Clone, Copy)]
+pub struct Cell {
+    pub(crate) symbl: char,
+    pub(crate) color: (u8, u8, u8),
+    pub(crate) is_head: bool,
+}
+
+impl Cell {
+    pub const BLANK: Cell = Cell {
+        symbl: ' ',
+        color: (0, 0, 0),
+        is_head: false,
+    };
+    // Never looks like a drawn cell, so the cell is drawn again.
+    pub const INVALID: Cell = Cell {
+        symbl: '\0',
+        color: (0, 0, 0),
+        is_head: false,
+    };
+
+    /// The one of two overlapping cells that shows, heads first, then the
+    /// brighter.
+    pub fn top(self, other: Cell) -> Cell {
+        let key = |cell: &Cell| (cell.is_head, cell.brightnes(), cell.color, cell.symbl);
+        if key(&self) > key(&other) {
+            self
+        } else {
+            other
+        }
+    }
+    #[inline]
+    pub fn looks_like(&self, other: &Cell) -> bool {
+        self.symbl == other.symbl && (self.symbl == ' ' || self.color == other.color)
+    }
+    #[inline]
+    pub fn brightnes(&self) -> u8 {
+        if self.symbl == ' ' {
+            0
+        } else {
+            self.color.0.max(self.color.1).max(self.color.2)
+        }
+    }
+}
+
+/// What rains left on a cell, fading out over `decay_time`.
+#[derive(Debug, Clone, Copy)]
+pub struct Glow {
+    pub(crate) symbl: char,
+    pub(crate) color: (u8, u8, u8),
+    // Simulation time the color was lit.
+    pub(crate) lit_at: f32,
+}
+
+impl Glow {
+    pub const NONE: Glow = Glow {
+        symbl: ' ',
+        color: (0, 0, 0),
+        lit_at: f32::NEG_INFINITY,
+    };
+
+    /// The cell faded for `time - lit_at` seconds, if anything is left.
+    #[inline]
+    pub fn faded(&self, time: f32, decay_time: f32, space: ColorSpace) -> Option<Cell> {
+        let intensity = 1.0 - (time - self.lit_at) / decay_time;
+        let color = RColor::from(self.color)
+            .scale(intensity.max(0.0), space)
+            .tuple();
+        if intensity <= 0.0 || color == (0, 0, 0) {
+            return None;
+        }
+
+        Some(Cell {
+            symbl: self.symbl,
+            color,
+            is_head: false,
+        })
+    }
+}
+
+// Copy of `grid` sized `new`, keeping the cells it shares with `old`.
+fn resize_grid<T: Copy>(
+    grid: &[T],
+    old: (u16, u16),
+    new: (u16, u16),
+    mut fill: impl FnMut() -> T,
+) -> Vec<T> {
+    let mut resized = Vec::with_capacity(new.0 as usize * new.1 as usize);
+    for y in 0..new.1 as usize {
+        for x in 0..new.0 as usize {
+            resized.push(if x < old.0 as usize && y < old.1 as usize {
+                grid[y * old.0 as usize + x]
+            } else {
+                fill()
+            });
+        }
+    }
+    resized
+}
+
+/// Space colors are mixed and scaled in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    /// Gamma-encoded components, as before.
+    Srgb,
+    Linear,
+    Hsv,
+    Hsl,
+    Oklab,
+}
+
+impl ColorSpace {
+    pub const NAMES: [(&'static str, ColorSpace); 5] = [
+        ("srgb", ColorSpace::Srgb),
+        ("linear", ColorSpace::Linear),
+        ("hsv", ColorSpace::Hsv),
+        ("hsl", ColorSpace::Hsl),
+        ("oklab", ColorSpace::Oklab),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        value_of(&Self::NAMES, name)
+    }
+    pub fn name(&self) -> &'static str {
+        name_of(&Self::NAMES, self).unwrap()
+    }
+}
+
+/// What the hue of the rain colors changes with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HueMode {
+    /// Colors as configured.
+    None,
+    /// Across the columns.
+    Column,
+    /// A random shift per rain.
+    Rain,
+    /// The whole screen at once, spread along the trails.
+    Time,
+}
+
+impl HueMode {
+    pub const NAMES: [(&'static str, HueMode); 4] = [
+        ("none", HueMode::None),
+        ("column", HueMode::Column),
+        ("rain", HueMode::Rain),
+        ("time", HueMode::Time),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        value_of(&Self::NAMES, name)
+    }
+    pub fn name(&self) -> &'static str {
+        name_of(&Self::NAMES, self).unwrap()
+    }
+}
+
+#[inline]
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+#[inline]
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+#[inline]
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+// Along the shorter arc, in degrees.
+#[inline]
+fn lerp_hue(a: f32, b: f32, t: f32) -> f32 {
+    let delta = (b - a + 540.0).rem_euclid(360.0) - 180.0;
+    (a + delta * t).rem_euclid(360.0)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 #[repr(transparent)]
 pub struct RColor(pub (i16, i16, i16));
 impl RColor {
@@ -40,6 +433,163 @@ impl RColor {
             (self.0 .2 as f32 * dc) as i16,
         ))
     }
+
+    #[inline]
+    fn unit(&self) -> [f32; 3] {
+        let (r, g, b) = self.tuple();
+        [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0]
+    }
+    #[inline]
+    fn from_unit([r, g, b]: [f32; 3]) -> Self {
+        let component = |c: f32| (c * 255.0).round().clamp(-255.0, 510.0) as i16;
+        Self((component(r), component(g), component(b)))
+    }
+
+    /// Linear-light components in `0.0..=1.0`.
+    pub fn to_linear(&self) -> [f32; 3] {
+        let [r, g, b] = self.unit();
+        [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b)]
+    }
+    pub fn from_linear([r, g, b]: [f32; 3]) -> Self {
+        let encode = |c: f32| linear_to_srgb(c.clamp(0.0, 1.0));
+        Self::from_unit([encode(r), encode(g), encode(b)])
+    }
+
+    /// Hue in degrees, saturation and value in `0.0..=1.0`.
+    pub fn to_hsv(&self) -> [f32; 3] {
+        let [r, g, b] = self.unit();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let chroma = max - min;
+        let hue = if chroma == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / chroma).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / chroma + 2.0)
+        } else {
+            60.0 * ((r - g) / chroma + 4.0)
+        };
+        let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+        [hue, saturation, max]
+    }
+    /// Hue turned by `degrees`, with `saturation` in place of the own one
+    /// when set.
+    pub fn shift_hue(&self, degrees: f32, saturation: Option<f32>) -> Self {
+        let [hue, own_saturation, value] = self.to_hsv();
+        Self::from_hsv([hue + degrees, saturation.unwrap_or(own_saturation), value])
+    }
+    pub fn from_hsv([hue, saturation, value]: [f32; 3]) -> Self {
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - (hue.rem_euclid(2.0) - 1.0).abs());
+        let (r, g, b) = match hue as u8 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let min = value - chroma;
+        Self::from_unit([r + min, g + min, b + min])
+    }
+
+    /// Hue in degrees, saturation and lightness in `0.0..=1.0`.
+    pub fn to_hsl(&self) -> [f32; 3] {
+        let [hue, saturation, value] = self.to_hsv();
+        let lightness = value * (1.0 - saturation / 2.0);
+        let saturation = if lightness == 0.0 || lightness == 1.0 {
+            0.0
+        } else {
+            (value - lightness) / lightness.min(1.0 - lightness)
+        };
+        [hue, saturation, lightness]
+    }
+    pub fn from_hsl([hue, saturation, lightness]: [f32; 3]) -> Self {
+        let value = lightness + saturation * lightness.min(1.0 - lightness);
+        let saturation = if value == 0.0 {
+            0.0
+        } else {
+            2.0 * (1.0 - lightness / value)
+        };
+        Self::from_hsv([hue, saturation, value])
+    }
+
+    /// OKLab lightness and the `a`, `b` opponent axes.
+    pub fn to_oklab(&self) -> [f32; 3] {
+        let [r, g, b] = self.to_linear();
+        let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+        let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+        let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+        [
+            0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+            1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+            0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+        ]
+    }
+    pub fn from_oklab([lightness, a, b]: [f32; 3]) -> Self {
+        let l = (lightness + 0.396_337_78 * a + 0.215_803_76 * b).powi(3);
+        let m = (lightness - 0.105_561_346 * a - 0.063_854_17 * b).powi(3);
+        let s = (lightness - 0.089_484_18 * a - 1.291_485_5 * b).powi(3);
+        Self::from_linear([
+            4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s,
+            -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s,
+            -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s,
+        ])
+    }
+
+    /// Color `t` of the way from `self` to `other` in `space`.
+    pub fn mix(&self, other: &RColor, t: f32, space: ColorSpace) -> Self {
+        let mix3 = |a: [f32; 3], b: [f32; 3]| {
+            [
+                lerp(a[0], b[0], t),
+                lerp(a[1], b[1], t),
+                lerp(a[2], b[2], t),
+            ]
+        };
+        // Gray has no hue of its own, so it takes the other one.
+        let mix_hue = |a: [f32; 3], b: [f32; 3]| {
+            let hue = match (a[1] == 0.0, b[1] == 0.0) {
+                (true, false) => b[0],
+                (false, true) => a[0],
+                _ => lerp_hue(a[0], b[0], t),
+            };
+            [hue, lerp(a[1], b[1], t), lerp(a[2], b[2], t)]
+        };
+
+        match space {
+            ColorSpace::Srgb => self.add(&other.sub(self).interpolate(t)),
+            ColorSpace::Linear => Self::from_linear(mix3(self.to_linear(), other.to_linear())),
+            ColorSpace::Hsv => Self::from_hsv(mix_hue(self.to_hsv(), other.to_hsv())),
+            ColorSpace::Hsl => Self::from_hsl(mix_hue(self.to_hsl(), other.to_hsl())),
+            ColorSpace::Oklab => Self::from_oklab(mix3(self.to_oklab(), other.to_oklab())),
+        }
+    }
+
+    /// `self` with its brightness scaled by `koef` in `space`.
+    pub fn scale(&self, koef: f32, space: ColorSpace) -> Self {
+        match space {
+            ColorSpace::Srgb => self.interpolate(koef),
+            ColorSpace::Linear => {
+                let [r, g, b] = self.to_linear();
+                Self::from_linear([r * koef, g * koef, b * koef])
+            }
+            ColorSpace::Hsv => {
+                let [hue, saturation, value] = self.to_hsv();
+                Self::from_hsv([hue, saturation, (value * koef).clamp(0.0, 1.0)])
+            }
+            ColorSpace::Hsl => {
+                let [hue, saturation, lightness] = self.to_hsl();
+                Self::from_hsl([hue, saturation, (lightness * koef).clamp(0.0, 1.0)])
+            }
+            ColorSpace::Oklab => {
+                let [lightness, a, b] = self.to_oklab();
+                Self::from_oklab([lightness * koef, a * koef, b * koef])
+            }
+        }
+    }
+
     #[inline]
     pub const fn add(&self, lft: &RColor) -> Self {
         Self((
@@ -58,6 +608,65 @@ impl RColor {
     }
 }
 
+/// Color stops by position, `0.0` at the tail and `1.0` at the head.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient(pub Vec<(f32, RColor)>);
+
+impl Gradient {
+    /// Color at `t` mixed in `space`, extended past the first and the last
+    /// stop.
+    pub fn at(&self, t: f32, space: ColorSpace) -> RColor {
+        let stops = &self.0;
+        match stops.len() {
+            0 => RColor::default(),
+            1 => stops[0].1.clone(),
+            len => {
+                let idx = stops[1..len - 1]
+                    .iter()
+                    .position(|&(pos, _)| t < pos)
+                    .unwrap_or(len - 2);
+                let ((start_pos, start), (end_pos, end)) = (&stops[idx], &stops[idx + 1]);
+                let t = if end_pos > start_pos {
+                    (t - start_pos) / (end_pos - start_pos)
+                } else {
+                    1.0
+                };
+                start.mix(end, t, space)
+            }
+        }
+    }
+}
+
+impl Gradient {
+    /// The same gradient ending in `color`.
+    pub fn with_end(&self, color: &RColor) -> Self {
+        let mut stops = self.0.clone();
+        match stops.last_mut() {
+            Some((_, end)) => *end = color.clone(),
+            None => stops.push((1.0, color.clone())),
+        }
+        Self(stops)
+    }
+}
+
+/// Colors a rain picks its own from when it spawns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorChoice {
+    /// Anywhere between two colors.
+    Range(RColor, RColor),
+    /// One of the colors.
+    Palette(Vec<RColor>),
+}
+
+impl ColorChoice {
+    pub fn pick<R: Rng>(&self, rng: &mut R, space: ColorSpace) -> RColor {
+        match self {
+            ColorChoice::Range(start, end) => start.mix(end, rng.gen(), space),
+            ColorChoice::Palette(colors) => colors.choose(rng).cloned().unwrap_or_default(),
+        }
+    }
+}
+
 impl From<(u8, u8, u8)> for RColor {
     #[inline]
     fn from(val: (u8, u8, u8)) -> Self {
@@ -78,21 +687,86 @@ pub struct Rmatrix {
     pub(crate) height: u16,
     pub(crate) rains: Vec<Rain>,
     pub(crate) symbls: Vec<char>,
+    // What is currently on the terminal, row by row.
+    pub(crate) screen: Vec<Cell>,
+    // Frames each screen cell has been waiting for its update.
+    pub(crate) deferred: Vec<u8>,
+    // Glyph of every cell, row by row, lit by the passing rains.
+    pub(crate) glyphs: Vec<char>,
+    // Afterglow of every cell, row by row.
+    pub(crate) glow: Vec<Glow>,
 
     pub(crate) rng: StdRng,
-    pub(crate) speed: Range<u16>,
+    pub(crate) speed: Range<f32>,
     pub(crate) len: Range<u16>,
 
     pub(crate) start_gradient_color: Option<RColor>,
+    // Replaces `start_gradient_color..color` when set.
+    pub(crate) gradient: Option<Gradient>,
+    pub(crate) color_space: ColorSpace,
+    pub(crate) hue_mode: HueMode,
+    // Degrees per second the hue cycles by and degrees it varies by across
+    // the columns, the rains or the trail.
+    pub(crate) hue_speed: f32,
+    pub(crate) hue_spread: f32,
+    pub(crate) hue_saturation: Option<f32>,
     pub(crate) color: RColor,
     pub(crate) head_color: Option<RColor>,
+    // Replace `color` and `head_color` per rain when set.
+    pub(crate) rain_color: Option<ColorChoice>,
+    pub(crate) rain_head_color: Option<ColorChoice>,
     pub(crate) interpolate_color_koef: Option<f32>,
+    // `None` walks a gradient linearly and keeps a single color unfaded.
+    pub(crate) curve: Option<BrightnessCurve>,
+    // Cells from the head drawn in the head color.
+    pub(crate) head_cells: u16,
     pub(crate) min_brightnes: Option<f32>,
     pub(crate) density: f32,
+    // Degrees, clockwise from the right.
+    pub(crate) direction: f32,
+    // Drift in cells per second.
+    pub(crate) wind: (f32, f32),
+    // Downward acceleration in cells per second squared.
+    pub(crate) gravity: f32,
+    // `(x, y, strength)` with `x` and `y` in shares of the screen size,
+    // negative strength repels.
+    pub(crate) attractors: Vec<(f32, f32, f32)>,
+    pub(crate) spawn: SpawnStrategy,
+    // Free rows kept between rains of one column.
+    pub(crate) min_gap: u16,
+    pub(crate) density_pattern: DensityPattern,
+    // Pattern periods per column, periods per second and share of `density`
+    // the pattern adds or takes away.
+    pub(crate) pattern_scale: f32,
+    pub(crate) pattern_speed: f32,
+    pub(crate) pattern_amplitude: f32,
+    // Seconds simulated so far.
+    pub(crate) time: f32,
 
     pub(crate) is_bold: bool,
     pub(crate) is_default_rain: bool,
+    pub(crate) is_glyph_grid: bool,
+    // Chance per second for a glyph to change, reaching
+    // `head_mutation_rate` toward the head.
+    pub(crate) mutation_rate: f32,
+    pub(crate) head_mutation_rate: Option<f32>,
+    // Seconds for a cell to fade out after the rain passed.
+    pub(crate) decay_time: Option<f32>,
+    pub(crate) blend: BlendMode,
+    pub(crate) is_utf8: bool,
     pub(crate) delay: time::Duration,
+    pub(crate) tick_rate: f32,
+
+    pub(crate) is_adaptive: bool,
+    pub(crate) show_stats: bool,
+    // Share of density and trail length kept under load, set by the pacer.
+    pub(crate) quality: f32,
+    pub(crate) max_bytes_per_frame: Option<usize>,
+    // `None` asks the terminal whether it supports synchronized output.
+    pub(crate) synchronized_output: Option<bool>,
+    // Only kept to write the config back, errors go to the file opened while
+    // reading the config.
+    pub(crate) error_report_file: Option<String>,
 }
 
 impl Default for Rmatrix {
@@ -106,22 +780,61 @@ impl Default for Rmatrix {
             height: 0,
             rains: Vec::new(),
             symbls: vec![],
+            screen: Vec::new(),
+            deferred: Vec::new(),
+            glyphs: Vec::new(),
+            glow: Vec::new(),
 
             rng: std_rng,
-            speed: 1..3,
+            speed: 60.0..180.0,
             len: 5..20,
 
             start_gradient_color: Some(RColor::new(0, 0, 0)),
+            gradient: None,
+            color_space: ColorSpace::Srgb,
+            hue_mode: HueMode::None,
+            hue_speed: 30.0,
+            hue_spread: 360.0,
+            hue_saturation: None,
             color: RColor::new(0, 255, 0),
             head_color: Some(RColor::new(255, 255, 255)),
+            rain_color: None,
+            rain_head_color: None,
 
             interpolate_color_koef: Some(1.5),
+            curve: None,
+            head_cells: 1,
             min_brightnes: Some(0.1),
             density: 0.7,
+            direction: 90.0,
+            wind: (0.0, 0.0),
+            gravity: 0.0,
+            attractors: Vec::new(),
+            spawn: SpawnStrategy::Balanced,
+            min_gap: 1,
+            density_pattern: DensityPattern::Flat,
+            pattern_scale: 0.05,
+            pattern_speed: 0.1,
+            pattern_amplitude: 0.8,
+            time: 0.0,
 
             is_bold: true,
             is_default_rain: true,
+            is_glyph_grid: false,
+            mutation_rate: 0.1,
+            head_mutation_rate: None,
+            decay_time: None,
+            blend: BlendMode::HeadOnTop,
+            is_utf8: true,
             delay: time::Duration::from_millis(16),
+            tick_rate: 120.0,
+
+            is_adaptive: true,
+            show_stats: false,
+            quality: 1.0,
+            max_bytes_per_frame: None,
+            synchronized_output: None,
+            error_report_file: None,
         };
 
         rmatrix.set_utf8();
@@ -132,20 +845,59 @@ impl Default for Rmatrix {
 }
 
 impl Rmatrix {
+    /// Keep the rains and glowing cells that are still inside `width` and
+    /// `height`, then trim or add rains to match the density.
     pub fn resize(&mut self, width: u16, height: u16) {
+        let (old, new) = ((self.width, self.height), (width, height));
+        // Terminals may reflow or clear on resize, so the next frame redraws
+        // every cell instead of clearing the screen.
+        self.screen = vec![Cell::INVALID; width as usize * height as usize];
+        self.deferred = vec![0; self.screen.len()];
+        self.glow = resize_grid(&self.glow, old, new, || Glow::NONE);
+
+        let (symbls, rng) = (&self.symbls, &mut self.rng);
+        self.glyphs = resize_grid(&self.glyphs, old, new, || {
+            *symbls.choose(rng).expect("Invalid choose")
+        });
+
         self.width = width;
         self.height = height;
-        self.rains = Vec::with_capacity(self.expected_capacity());
+        self.rains
+            .retain(|rain| rain.x < width as f32 && rain.y < height as f32);
+        while self.rains.len() > self.expected_capacity() {
+            let idx = self.rng.gen_range(0..self.rains.len());
+            self.rains.swap_remove(idx);
+        }
         self.fill_rains();
     }
 
+    /// Force the cells of `row` in `columns` to be drawn again, e.g. after
+    /// something else has been printed over them.
+    pub fn invalidate_screen(&mut self, row: u16, columns: Range<u16>) {
+        if row >= self.height {
+            return;
+        }
+
+        let start = row as usize * self.width as usize;
+        let columns = columns.start.min(self.width) as usize..columns.end.min(self.width) as usize;
+        for cell in self.screen[start + columns.start..start + columns.end].iter_mut() {
+            *cell = Cell::INVALID;
+        }
+    }
+
     #[inline]
     pub fn delay(&self) -> time::Duration {
         self.delay
     }
+    #[inline]
+    pub fn tick(&self) -> time::Duration {
+        let tick_rate = self.tick_rate.clamp(*TICK_RATES.start(), *TICK_RATES.end());
+        time::Duration::from_secs_f32(1.0 / tick_rate)
+    }
 
     #[inline]
     pub fn set_utf8(&mut self) {
+        self.is_utf8 = true;
         self.symbls = vec![
             'ﾊ', 'ﾐ', 'ﾋ', 'ｰ', 'ｳ', 'ｼ', 'ﾅ', 'ﾓ', 'ﾆ', 'ｻ', 'ﾜ', 'ﾂ', 'ｵ', 'ﾘ', 'ｱ', 'ﾎ', 'ﾃ',
             'ﾏ', 'ｹ', 'ﾒ', 'ｴ', 'ｶ', 'ｷ', 'ﾑ', 'ﾕ', 'ﾗ', 'ｾ', 'ﾈ', 'ｽ', 'ﾀ', 'ﾇ', 'ﾍ', 'ﾄ', '0',
@@ -155,9 +907,11 @@ impl Rmatrix {
         ];
 
         assert!(self.symbls.len() <= u16::MAX as usize);
+        self.shuffle_glyphs();
     }
     #[inline]
     pub fn set_ascii(&mut self) {
+        self.is_utf8 = false;
         self.symbls = vec![
             '0', '1', '2', '3', '4', '5', '7', '8', '9', 'Z', ':', '.', '･', '=', '*', '+', '-',
             '<', '>', '¦', '|', '╌', ' ', '\"', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'l',
@@ -165,27 +919,230 @@ impl Rmatrix {
         ];
 
         assert!(self.symbls.len() <= u16::MAX as usize);
+        self.shuffle_glyphs();
+    }
+
+    #[inline]
+    fn random_symbl(&mut self) -> char {
+        *self.symbls.choose(&mut self.rng).expect("Invalid choose")
+    }
+    // Refill the glyph grid from the current symbols.
+    fn shuffle_glyphs(&mut self) {
+        for idx in 0..self.glyphs.len() {
+            self.glyphs[idx] = self.random_symbl();
+        }
+    }
+    // Change about `mutation_rate` of the grid glyphs per second.
+    fn mutate_glyphs(&mut self, dt: f32) {
+        let expected = self.glyphs.len() as f32 * self.mutation_rate * dt;
+        let mut mutations = expected as usize;
+        if self.rng.gen::<f32>() < expected.fract() {
+            mutations += 1;
+        }
+
+        for _ in 0..mutations {
+            let idx = self.rng.gen_range(0..self.glyphs.len());
+            self.glyphs[idx] = self.random_symbl();
+        }
     }
 
     #[inline]
     fn fill_rains(&mut self) {
         while self.possible_add() {
-            let x = self.rng.gen_range(0..self.width);
-            let y = self.rng.gen_range(0..self.height);
-            self.add_rain(x, y);
+            let progress = self.rng.gen::<f32>();
+            match self.spawn_lane(progress) {
+                Some(lane) => {
+                    let progress = progress * self.lane_len(lane);
+                    self.add_rain(lane, progress)
+                }
+                None => break,
+            }
         }
     }
+
+    /// Cells a rain moves per step, the longer axis is always one cell.
+    pub fn step(&self) -> (f32, f32) {
+        let angle = self.direction.to_radians();
+        let (mut dx, mut dy) = (angle.cos(), angle.sin());
+        // Keep the named directions exact.
+        if dx.abs() < 1e-4 {
+            dx = 0.0;
+        }
+        if dy.abs() < 1e-4 {
+            dy = 0.0;
+        }
+        let major = dx.abs().max(dy.abs());
+        (dx / major, dy / major)
+    }
+
+    // Lanes along the top or bottom edge, then along the left or right one.
+    #[inline]
+    fn lanes(&self) -> (u16, u16) {
+        let (dx, dy) = self.step();
+        (
+            if dy != 0.0 { self.width } else { 0 },
+            if dx != 0.0 { self.height } else { 0 },
+        )
+    }
+    #[inline]
+    pub fn lane_count(&self) -> u16 {
+        let (x_lanes, y_lanes) = self.lanes();
+        x_lanes + y_lanes
+    }
+
+    /// Where rains of `lane` enter the screen.
+    pub fn lane_start(&self, lane: u16) -> (f32, f32) {
+        let (dx, dy) = self.step();
+        let (x_lanes, _) = self.lanes();
+        let edge = |d: f32, size: u16| if d < 0.0 { size as f32 - 0.5 } else { 0.5 };
+        if lane < x_lanes {
+            (lane as f32 + 0.5, edge(dy, self.height))
+        } else {
+            (edge(dx, self.width), (lane - x_lanes) as f32 + 0.5)
+        }
+    }
+
+    // Steps from the start of `lane` to the opposite edge.
+    fn lane_len(&self, lane: u16) -> f32 {
+        let (dx, dy) = self.step();
+        let (x, y) = self.lane_start(lane);
+        let until = |pos: f32, d: f32, size: u16| {
+            if d > 0.0 {
+                (size as f32 - pos) / d
+            } else if d < 0.0 {
+                pos / -d
+            } else {
+                f32::INFINITY
+            }
+        };
+        until(x, dx, self.width).min(until(y, dy, self.height))
+    }
+
+    /// Lane of the head `(x, y)` and the steps it is past the lane start.
+    pub fn lane_of(&self, x: f32, y: f32) -> (u16, f32) {
+        let (dx, dy) = self.step();
+        let (x_lanes, y_lanes) = self.lanes();
+        let since = |pos: f32, d: f32, size: u16| {
+            if d > 0.0 {
+                (pos - 0.5) / d
+            } else if d < 0.0 {
+                (pos - (size as f32 - 0.5)) / d
+            } else {
+                f32::INFINITY
+            }
+        };
+        let (x_steps, y_steps) = (since(y, dy, self.height), since(x, dx, self.width));
+
+        if y_lanes == 0 || (x_lanes != 0 && x_steps <= y_steps) {
+            let lane = (x - x_steps * dx)
+                .floor()
+                .clamp(0.0, x_lanes.saturating_sub(1) as f32);
+            (lane as u16, x_steps)
+        } else {
+            let lane = (y - y_steps * dy)
+                .floor()
+                .clamp(0.0, y_lanes.saturating_sub(1) as f32);
+            (x_lanes + lane as u16, y_steps)
+        }
+    }
+
+    // Whether a rain with its head `progress` steps into `lane` keeps
+    // `min_gap` to the rains of the lane, assuming the longest length.
+    fn is_gap_free(&self, lane: u16, progress: f32) -> bool {
+        let top = progress - self.len.end as f32 - self.min_gap as f32;
+        let bottom = progress + self.min_gap as f32;
+        self.rains.iter().all(|rain| {
+            let (rain_lane, rain_progress) = self.lane_of(rain.x, rain.y);
+            rain_lane != lane || rain_progress < top || rain_progress - rain.length as f32 > bottom
+        })
+    }
+
+    /// Lane for a new rain with its head at the `progress` share of the
+    /// lane, `None` when the tried lanes are all too crowded.
+    pub fn spawn_lane(&mut self, progress: f32) -> Option<u16> {
+        let lane_count = self.lane_count();
+        if lane_count == 0 {
+            return None;
+        }
+
+        let candidates = match self.spawn {
+            SpawnStrategy::Uniform => (0..SPAWN_ATTEMPTS)
+                .map(|_| self.rng.gen_range(0..lane_count))
+                .collect::<Vec<_>>(),
+            SpawnStrategy::Balanced => {
+                let mut occupancy = vec![0u16; lane_count as usize];
+                for rain in self.rains.iter() {
+                    occupancy[self.lane_of(rain.x, rain.y).0 as usize] += 1;
+                }
+
+                let mut lanes = (0..lane_count).collect::<Vec<_>>();
+                lanes.shuffle(&mut self.rng);
+                lanes.sort_by_key(|&lane| occupancy[lane as usize]);
+                lanes.truncate(SPAWN_ATTEMPTS);
+                lanes
+            }
+            SpawnStrategy::Noise => {
+                let mut lanes = Vec::with_capacity(SPAWN_ATTEMPTS);
+                for _ in 0..SPAWN_ATTEMPTS * 4 {
+                    let lane = self.rng.gen_range(0..lane_count);
+                    let noise = noise::perlin(
+                        lane as f32 * SPAWN_NOISE_SCALE,
+                        self.time * SPAWN_NOISE_SPEED,
+                    );
+                    if self.rng.gen::<f32>() < (noise + 1.0) * 0.5 {
+                        lanes.push(lane);
+                        if lanes.len() == SPAWN_ATTEMPTS {
+                            break;
+                        }
+                    }
+                }
+                lanes
+            }
+        };
+
+        let is_flat = self.density_pattern == DensityPattern::Flat;
+        let max_density = 1.0 + self.pattern_amplitude.max(0.0);
+        // Sparse parts of the pattern turn most spawns away.
+        candidates.into_iter().find(|&lane| {
+            (is_flat || self.rng.gen::<f32>() * max_density < self.density_at(lane))
+                && self.is_gap_free(lane, progress * self.lane_len(lane))
+        })
+    }
+
+    /// Factor of `density` in `lane` at the current time.
+    pub fn density_at(&self, lane: u16) -> f32 {
+        let (x, t) = (
+            lane as f32 * self.pattern_scale,
+            self.time * self.pattern_speed,
+        );
+        let wave = match self.density_pattern {
+            DensityPattern::Flat => return 1.0,
+            DensityPattern::Noise => noise::perlin(x, t),
+            DensityPattern::Wave => ((x - t) * core::f32::consts::TAU).sin(),
+        };
+        (1.0 + wave * self.pattern_amplitude).max(0.0)
+    }
+
     #[inline]
     fn expected_capacity(&self) -> usize {
-        (self.width as f32 * self.density) as usize
+        let density = if self.density_pattern == DensityPattern::Flat {
+            self.lane_count() as f32
+        } else {
+            (0..self.lane_count())
+                .map(|lane| self.density_at(lane))
+                .sum()
+        };
+        (density * self.density * self.quality) as usize
     }
     #[inline]
     fn possible_add(&self) -> bool {
         self.rains.len() < self.expected_capacity()
     }
+    /// Add a rain with its head `progress` steps into `lane`.
     #[inline]
-    pub fn add_rain(&mut self, x: u16, y: u16) {
+    pub fn add_rain(&mut self, lane: u16, progress: f32) {
         let length = self.rng.gen_range(self.len.clone());
+        let length = ((length as f32 * self.quality) as u16).max(1);
         let speed = self.rng.gen_range(self.speed.clone());
         let symbl_pos = self.rng.gen_range(0..self.symbls.len() as u16);
 
@@ -195,38 +1152,298 @@ impl Rmatrix {
             None
         };
 
+        let (rng, space) = (&mut self.rng, self.color_space);
+        let color = self
+            .rain_color
+            .as_ref()
+            .map(|choice| choice.pick(rng, space));
+        let head_color = self
+            .rain_head_color
+            .as_ref()
+            .map(|choice| choice.pick(rng, space));
+
+        let (x, y) = self.lane_start(lane);
+        let (dx, dy) = self.step();
+        let (x, y) = (x + dx * progress, y + dy * progress);
+        // As if the rain had come straight from behind.
+        let trail = (0..=length)
+            .rev()
+            .map(|steps| {
+                let steps = steps as f32;
+                (
+                    (x - dx * steps).floor() as i32,
+                    (y - dy * steps).floor() as i32,
+                )
+            })
+            .collect::<VecDeque<_>>();
+        let glyphs = trail.iter().map(|_| self.random_symbl()).collect();
+
         self.rains.push(Rain {
             x,
             y,
-            speed,
+            vx: dx * speed,
+            vy: dy * speed,
             length,
+            trail,
+            glyphs,
+            entered: (progress + 0.5).floor() as i64,
             symbl_pos,
             brightnes,
+            hue: self.rng.gen(),
+            color,
+            head_color,
         });
     }
-    pub fn update(&mut self) {
+
+    /// Degrees the color of the trail cell `steps` behind the head of `rain`
+    /// is turned by, `None` when the hue does not change.
+    pub fn hue_shift(&self, rain: &Rain, (x, y): (i32, i32), steps: usize) -> Option<f32> {
+        let share = match self.hue_mode {
+            HueMode::None => return None,
+            HueMode::Column => {
+                let (lane, _) = self.lane_of(x as f32 + 0.5, y as f32 + 0.5);
+                lane as f32 / self.lane_count().max(1) as f32
+            }
+            HueMode::Rain => rain.hue,
+            HueMode::Time => steps as f32 / rain.length.max(1) as f32,
+        };
+        Some(share * self.hue_spread + self.time * self.hue_speed)
+    }
+
+    #[inline]
+    fn is_visible(&self, (x, y): (i32, i32)) -> bool {
+        x >= 0 && y >= 0 && x < self.width as i32 && y < self.height as i32
+    }
+
+    // Acceleration of gravity and the attractors at `(x, y)`.
+    fn acceleration(&self, x: f32, y: f32) -> (f32, f32) {
+        let (mut ax, mut ay) = (0.0, self.gravity);
+        for &(attractor_x, attractor_y, strength) in self.attractors.iter() {
+            let (dx, dy) = (
+                attractor_x * self.width as f32 - x,
+                attractor_y * self.height as f32 - y,
+            );
+            let distance = (dx * dx + dy * dy).sqrt().max(1.0);
+            // Falls off with the distance, and once more to normalize.
+            let koef = strength / (distance * distance);
+            ax += dx * koef;
+            ay += dy * koef;
+        }
+        (ax, ay)
+    }
+
+    pub fn update(&mut self, dt: time::Duration) {
+        let dt = dt.as_secs_f32();
+        self.time += dt;
+        if self.is_glyph_grid && !self.glyphs.is_empty() {
+            self.mutate_glyphs(dt);
+        }
+
         let mut len = self.rains.len();
         let mut idx = 0;
         while idx < len {
-            let head = &mut self.rains[idx];
-
-            if head.y.saturating_sub(head.length).saturating_sub(1) > self.height {
+            let head = &self.rains[idx];
+            if head.trail.iter().all(|&cell| !self.is_visible(cell)) {
                 self.rains.swap_remove(idx);
                 len -= 1;
                 continue;
             }
 
-            head.y = head.y.saturating_add(head.speed);
+            let (ax, ay) = self.acceleration(head.x, head.y);
+            let wind = self.wind;
+            let head = &mut self.rains[idx];
+            head.vx += ax * dt;
+            head.vy += ay * dt;
+            head.x += (head.vx + wind.0) * dt;
+            head.y += (head.vy + wind.1) * dt;
+
+            // Every cell on the way, so fast rains leave no holes.
+            let (from_x, from_y) = *head.trail.back().unwrap();
+            let (to_x, to_y) = (head.x.floor() as i32, head.y.floor() as i32);
+            let cells = (to_x - from_x).abs().max((to_y - from_y).abs());
+            for cell in 1..=cells {
+                let t = cell as f32 / cells as f32;
+                head.trail.push_back((
+                    from_x + ((to_x - from_x) as f32 * t).round() as i32,
+                    from_y + ((to_y - from_y) as f32 * t).round() as i32,
+                ));
+                head.glyphs
+                    .push_back(*self.symbls.choose(&mut self.rng).expect("Invalid choose"));
+                head.entered += 1;
+            }
+            while head.trail.len() > head.length as usize + 1 {
+                head.trail.pop_front();
+                head.glyphs.pop_front();
+            }
+
+            if !self.is_default_rain || self.is_glyph_grid {
+                let head_steps = head.trail.len() - 1;
+                let head_rate = self.head_mutation_rate.unwrap_or(self.mutation_rate);
+                for steps in 0..=head_steps {
+                    let closeness = 1.0 - steps as f32 / head.length as f32;
+                    let head_boost = (head_rate - self.mutation_rate) * closeness;
+                    // The grid mutates at `mutation_rate` on its own.
+                    let rate = if self.is_glyph_grid {
+                        head_boost
+                    } else {
+                        self.mutation_rate + head_boost
+                    };
+                    if self.rng.gen::<f32>() >= rate * dt {
+                        continue;
+                    }
+
+                    let symbl = *self.symbls.choose(&mut self.rng).expect("Invalid choose");
+                    let (x, y) = head.trail[head_steps - steps];
+                    if !self.is_glyph_grid {
+                        head.glyphs[head_steps - steps] = symbl;
+                    } else if x >= 0 && y >= 0 && x < self.width as i32 && y < self.height as i32 {
+                        self.glyphs[y as usize * self.width as usize + x as usize] = symbl;
+                    }
+                }
+            }
             idx += 1;
         }
 
         while self.possible_add() {
-            let x = self.rng.gen_range(0..self.width);
-            self.add_rain(x, 0);
+            match self.spawn_lane(0.0) {
+                Some(lane) => self.add_rain(lane, 0.0),
+                None => break,
+            }
         }
     }
 
-    pub(crate) fn as_crossterm_render(&mut self) -> RmatrixCrosstermRender {
+    pub(crate) fn as_crossterm_render(&mut self) -> RmatrixCrosstermRender<'_> {
         RmatrixCrosstermRender { rmatrix: self }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(symbl: char, color: (u8, u8, u8), is_head: bool) -> Cell {
+        Cell {
+            symbl,
+            color,
+            is_head,
+        }
+    }
+
+    // `cells` drawn one over another the way the renderer composes a frame.
+    fn compose(blend: BlendMode, cells: &[Cell]) -> (char, (u8, u8, u8), bool) {
+        let (mut frame, mut top) = (cells[0], cells[0]);
+        for &cell in cells[1..].iter() {
+            frame = blend.blend(frame, top, cell);
+            top = cell.top(top);
+        }
+        (frame.symbl, frame.color, frame.is_head)
+    }
+
+    fn permutations(cells: &[Cell]) -> Vec<Vec<Cell>> {
+        if cells.len() <= 1 {
+            return vec![cells.to_vec()];
+        }
+        let mut permutations = Vec::new();
+        for idx in 0..cells.len() {
+            let mut rest = cells.to_vec();
+            let first = rest.remove(idx);
+            for mut permutation in self::permutations(&rest) {
+                permutation.insert(0, first);
+                permutations.push(permutation);
+            }
+        }
+        permutations
+    }
+
+    // Every 17th level per channel, corners and grays included.
+    fn colors() -> Vec<RColor> {
+        let levels = (0..=255).step_by(17).collect::<Vec<_>>();
+        let mut colors = Vec::new();
+        for &r in levels.iter() {
+            for &g in levels.iter() {
+                for &b in levels.iter() {
+                    colors.push(RColor::new(r, g, b));
+                }
+            }
+        }
+        colors
+    }
+
+    #[test]
+    fn color_space_round_trips() {
+        for color in colors() {
+            let tuple = color.tuple();
+            assert_eq!(RColor::from_linear(color.to_linear()).tuple(), tuple);
+            assert_eq!(RColor::from_hsv(color.to_hsv()).tuple(), tuple);
+            assert_eq!(RColor::from_hsl(color.to_hsl()).tuple(), tuple);
+            assert_eq!(RColor::from_oklab(color.to_oklab()).tuple(), tuple);
+        }
+    }
+
+    #[test]
+    fn mix_keeps_end_points() {
+        let others = [
+            RColor::new(0, 0, 0),
+            RColor::new(128, 128, 128),
+            RColor::new(255, 0, 170),
+            RColor::new(0, 255, 255),
+        ];
+        for &(_, space) in ColorSpace::NAMES.iter() {
+            for color in colors() {
+                for other in others.iter() {
+                    assert_eq!(color.mix(other, 0.0, space).tuple(), color.tuple());
+                    assert_eq!(color.mix(other, 1.0, space).tuple(), other.tuple());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scale_keeps_color_at_one_and_blacks_out_at_zero() {
+        for &(_, space) in ColorSpace::NAMES.iter() {
+            for color in colors() {
+                assert_eq!(
+                    color.scale(1.0, space).tuple(),
+                    color.tuple(),
+                    "{:?}",
+                    space
+                );
+                assert_eq!(color.scale(0.0, space).tuple(), (0, 0, 0), "{:?}", space);
+            }
+        }
+    }
+
+    #[test]
+    fn blend_is_independent_of_drawing_order() {
+        // Equally bright trails, a dim head and colors that saturate.
+        let cells = [
+            cell('a', (200, 0, 0), false),
+            cell('b', (0, 200, 0), false),
+            cell('c', (100, 100, 100), true),
+            cell('d', (90, 0, 250), false),
+        ];
+        for &(_, blend) in BlendMode::NAMES.iter() {
+            let expected = compose(blend, &cells);
+            for permutation in permutations(&cells) {
+                assert_eq!(compose(blend, &permutation), expected, "{:?}", blend);
+            }
+        }
+    }
+
+    #[test]
+    fn blend_picks_cell_by_mode() {
+        let cells = [
+            cell('a', (200, 0, 0), false),
+            cell('c', (100, 100, 100), true),
+        ];
+        assert_eq!(compose(BlendMode::Max, &cells), ('a', (200, 0, 0), false));
+        assert_eq!(
+            compose(BlendMode::HeadOnTop, &cells),
+            ('c', (100, 100, 100), true)
+        );
+        assert_eq!(
+            compose(BlendMode::Additive, &cells),
+            ('c', (255, 100, 100), true)
+        );
+    }
+}
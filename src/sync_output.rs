@@ -0,0 +1,122 @@
+#![forbid(unsafe_code)]
+
+use std::io::Write;
+use std::{fmt, io, time};
+
+// DECRQM for synchronized output (mode 2026), followed by primary device
+// attributes, which every terminal answers, so the wait ends early on
+// terminals that ignore DECRQM.
+const QUERY: &str = "\x1B[?2026$p\x1B[c";
+const MODE_REPORT: &[u8] = b"\x1B[?2026;";
+
+/// Hold the terminal display until `EndSynchronizedUpdate`.
+pub struct BeginSynchronizedUpdate;
+
+impl crossterm::Command for BeginSynchronizedUpdate {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        f.write_str("\x1B[?2026h")
+    }
+
+    #[cfg(windows)]
+    fn execute_winapi(
+        &self,
+        _writer: impl FnMut() -> crossterm::Result<()>,
+    ) -> crossterm::Result<()> {
+        Ok(())
+    }
+}
+
+/// Show everything drawn since `BeginSynchronizedUpdate` at once.
+pub struct EndSynchronizedUpdate;
+
+impl crossterm::Command for EndSynchronizedUpdate {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        f.write_str("\x1B[?2026l")
+    }
+
+    #[cfg(windows)]
+    fn execute_winapi(
+        &self,
+        _writer: impl FnMut() -> crossterm::Result<()>,
+    ) -> crossterm::Result<()> {
+        Ok(())
+    }
+}
+
+// Mode state of the `CSI ? 2026 ; Ps $ y` report, if it has been received.
+fn mode_state(reply: &[u8]) -> Option<u8> {
+    let start = reply
+        .windows(MODE_REPORT.len())
+        .position(|window| window == MODE_REPORT)?
+        + MODE_REPORT.len();
+    match reply.get(start..start + 3)? {
+        [state, b'$', b'y'] => Some(state.wrapping_sub(b'0')),
+        _ => None,
+    }
+}
+
+// End of the device attributes report `CSI ? ... c`.
+fn is_reply_complete(reply: &[u8]) -> bool {
+    reply.ends_with(b"c") && reply.windows(2).any(|window| window == b"\x1B[")
+}
+
+/// Ask the terminal whether it supports synchronized output. Any error on
+/// the way counts as unsupported, so detection never ends the program.
+///
+/// Must run in raw mode before any event is read, since the reply arrives
+/// on the input.
+#[cfg(unix)]
+pub fn is_supported(timeout: time::Duration) -> bool {
+    // 1 - set, 2 - reset, 3 - permanently set; 0 and 4 mean unsupported.
+    query(timeout).is_ok_and(|reply| matches!(mode_state(&reply), Some(1..=3)))
+}
+
+// Send the query and collect the reply until it is complete or `timeout`
+// passes.
+#[cfg(unix)]
+fn query(timeout: time::Duration) -> io::Result<Vec<u8>> {
+    use mio::{unix::SourceFd, Events, Interest, Poll, Token};
+    use std::fs;
+    use std::io::Read;
+    use std::os::unix::io::AsRawFd;
+
+    // Unbuffered and read byte by byte, so nothing past the reply is taken
+    // from the events.
+    let mut tty = fs::File::open("/dev/tty")?;
+    let tty_fd = tty.as_raw_fd();
+    // Registered before the query is sent, so a failing poller leaves no
+    // reply behind in the events.
+    let mut poll = Poll::new()?;
+    poll.registry()
+        .register(&mut SourceFd(&tty_fd), Token(0), Interest::READABLE)?;
+    let mut events = Events::with_capacity(1);
+
+    let mut stdout = io::stdout();
+    stdout.write_all(QUERY.as_bytes())?;
+    stdout.flush()?;
+
+    let deadline = time::Instant::now() + timeout;
+    let mut reply = Vec::new();
+    let mut byte = [0];
+    while !is_reply_complete(&reply) {
+        let timeout = deadline.saturating_duration_since(time::Instant::now());
+        match poll.poll(&mut events, Some(timeout)) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            res => res?,
+        }
+        if events.is_empty() || tty.read(&mut byte)? == 0 {
+            break;
+        }
+        reply.push(byte[0]);
+        // Readiness is edge triggered, arm it again for the rest of the reply.
+        poll.registry()
+            .reregister(&mut SourceFd(&tty_fd), Token(0), Interest::READABLE)?;
+    }
+    Ok(reply)
+}
+
+/// Synchronized output is not detected outside of unix terminals.
+#[cfg(not(unix))]
+pub fn is_supported(_timeout: time::Duration) -> bool {
+    false
+}
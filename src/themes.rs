@@ -0,0 +1,136 @@
+#![forbid(unsafe_code)]
+
+use std::env;
+use std::path::PathBuf;
+
+/// Themes shipped with rsmatrix, as `config.rm` sources. Every theme sets
+/// the same keys, so switching themes leaves nothing behind.
+pub const BUILTIN_THEMES: [(&str, &str); 7] = [
+    (
+        "classic",
+        r#"
+color :: (0, 0, 0)..(0, 255, 0)
+gradient :: nil
+head_color :: (255, 255, 255)
+interpolate_color_koef :: 1.5
+min_brightnes :: 0.1
+utf8 :: true
+"#,
+    ),
+    (
+        "red_pill",
+        r#"
+color :: (0, 0, 0)..(255, 0, 0)
+gradient :: nil
+head_color :: (255, 210, 210)
+interpolate_color_koef :: 1.5
+min_brightnes :: 0.2
+utf8 :: true
+"#,
+    ),
+    (
+        "blue_pill",
+        r#"
+color :: (0, 0, 0)..(0, 90, 255)
+gradient :: nil
+head_color :: (210, 225, 255)
+interpolate_color_koef :: 1.5
+min_brightnes :: 0.2
+utf8 :: true
+"#,
+    ),
+    (
+        "amber",
+        r#"
+color :: (0, 0, 0)..(255, 176, 0)
+gradient :: nil
+head_color :: (255, 230, 170)
+interpolate_color_koef :: 1.2
+min_brightnes :: 0.3
+utf8 :: false
+"#,
+    ),
+    (
+        "cyberpunk",
+        r#"
+color :: (0, 255, 255)
+gradient :: ((20, 0, 40), (255, 0, 170), (0, 255, 255))
+head_color :: (255, 255, 255)
+interpolate_color_koef :: 1.5
+min_brightnes :: 0.3
+utf8 :: true
+"#,
+    ),
+    (
+        "monochrome",
+        r#"
+color :: (0, 0, 0)..(190, 190, 190)
+gradient :: nil
+head_color :: (255, 255, 255)
+interpolate_color_koef :: 1.5
+min_brightnes :: 0.2
+utf8 :: false
+"#,
+    ),
+    (
+        "ice",
+        r#"
+color :: (180, 240, 255)
+gradient :: ((0, 10, 30), (0, 120, 200), (180, 240, 255))
+head_color :: (255, 255, 255)
+interpolate_color_koef :: 1.3
+min_brightnes :: 0.2
+utf8 :: true
+"#,
+    ),
+];
+
+/// Source of the built-in theme `name`.
+pub fn builtin_theme(name: &str) -> Option<&'static str> {
+    BUILTIN_THEMES
+        .iter()
+        .find(|(theme_name, _)| theme_name.eq_ignore_ascii_case(name))
+        .map(|&(_, theme)| theme)
+}
+
+/// Whether `name` is made of `[A-Za-z0-9_-]` only, so it can't leave the
+/// themes directory.
+pub fn is_theme_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// `~/.config/rsmatrix/themes/<name>.rm`, preferred over a built-in theme
+/// of the same name. `None` for names that aren't theme names.
+pub fn theme_path(name: &str) -> Option<PathBuf> {
+    if !is_theme_name(name) {
+        return None;
+    }
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(
+        config_dir
+            .join("rsmatrix")
+            .join("themes")
+            .join(format!("{}.rm", name)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn theme_names_stay_in_themes_dir() {
+        assert!(is_theme_name("red_pill"));
+        assert!(is_theme_name("my-theme2"));
+        for name in ["", "../config", "a/b", "/etc/passwd", "..", "a.b", "a b"] {
+            assert!(!is_theme_name(name), "{}", name);
+            assert_eq!(theme_path(name), None);
+        }
+    }
+}